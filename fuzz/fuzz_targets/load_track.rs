@@ -0,0 +1,27 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use polymod::{track::Track, track_player::TrackPlayer, Error};
+
+// Every loader should be added here, so that they all get fuzzed.
+const LOADERS: &[fn(&[u8]) -> Result<Track, Error>] = &[
+    Track::from_it
+];
+
+fuzz_target!(|data: &[u8]| {
+    for loader in LOADERS {
+        let Ok(track) = loader(data) else {
+            continue;
+        };
+
+        // Anything that loads should also play without panicking. A low sample rate keeps each run short.
+        let mut player = TrackPlayer::new(&track, 8000);
+        player.set_max_loops(Some(0));
+
+        let mut buffer = vec![0.0; 4096];
+        player.render(&mut buffer);
+
+        player.seek_seconds(1.0);
+        player.render(&mut buffer);
+    }
+});
@@ -0,0 +1,220 @@
+use crate::{track::{self, Track, Pattern, TrackMetadata, SampleMetadata}, sample::Sample, midi::MidiMacros, Error, ModuleType};
+
+/// Builds a [`Track`] from scratch, checking that everything in it makes sense and calculating its length.
+pub struct TrackBuilder {
+    mod_type: ModuleType,
+
+    patterns: Vec<Pattern>,
+    orders: Vec<u8>,
+    samples: Vec<Sample>,
+
+    tempo: u8,
+    speed: u8,
+
+    global_volume: u8,
+    pans: Vec<u8>,
+    mix_volume: u8,
+
+    midi_macros: MidiMacros,
+
+    metadata: TrackMetadata
+}
+
+impl TrackBuilder {
+    /// Create a builder with the same defaults a new track has in Impulse Tracker.
+    pub fn new() -> Self {
+        Self {
+            mod_type: ModuleType::PMM,
+
+            patterns: Vec::new(),
+            orders: Vec::new(),
+            samples: Vec::new(),
+
+            tempo: 125,
+            speed: 6,
+
+            global_volume: 128,
+            pans: Vec::new(),
+            mix_volume: 48,
+
+            midi_macros: MidiMacros::it_default(),
+
+            metadata: TrackMetadata::default()
+        }
+    }
+
+    pub fn mod_type(mut self, mod_type: ModuleType) -> Self {
+        self.mod_type = mod_type;
+        self
+    }
+
+    /// Add a pattern. Patterns are numbered in the order they are added, starting at 0.
+    pub fn pattern(mut self, pattern: Pattern) -> Self {
+        self.patterns.push(pattern);
+        self
+    }
+
+    /// Add a pattern to the end of the order list. 254 and 255 can be used for skip and end markers.
+    pub fn order(mut self, pattern: u8) -> Self {
+        self.orders.push(pattern);
+        self
+    }
+
+    pub fn orders(mut self, orders: &[u8]) -> Self {
+        self.orders.extend_from_slice(orders);
+        self
+    }
+
+    /// Add a sample. Samples are numbered in the order they are added, starting at 0.
+    pub fn sample(mut self, sample: Sample) -> Self {
+        self.samples.push(sample);
+        self
+    }
+
+    /// The initial tempo, from 32 to 255.
+    pub fn tempo(mut self, tempo: u8) -> Self {
+        self.tempo = tempo;
+        self
+    }
+
+    /// The initial speed, in ticks per row.
+    pub fn speed(mut self, speed: u8) -> Self {
+        self.speed = speed;
+        self
+    }
+
+    /// The global volume, from 0 to 128.
+    pub fn global_volume(mut self, volume: u8) -> Self {
+        self.global_volume = volume;
+        self
+    }
+
+    /// The mix volume, from 0 to 128.
+    pub fn mix_volume(mut self, volume: u8) -> Self {
+        self.mix_volume = volume;
+        self
+    }
+
+    /// Set the initial pan of a channel, from 0 (left) to 64 (right), or 100 for surround. Adding 128 disables the
+    /// channel. Channels are centered by default.
+    pub fn pan(mut self, channel: u16, pan: u8) -> Self {
+        if self.pans.len() <= channel as usize {
+            self.pans.resize(channel as usize + 1, 32);
+        }
+
+        self.pans[channel as usize] = pan;
+        self
+    }
+
+    pub fn midi_macros(mut self, midi_macros: MidiMacros) -> Self {
+        self.midi_macros = midi_macros;
+        self
+    }
+
+    /// Set the track's metadata. Any samples without metadata are given an empty name.
+    pub fn metadata(mut self, metadata: TrackMetadata) -> Self {
+        self.metadata = metadata;
+        self
+    }
+
+    /// Check the track is valid and build it.
+    pub fn build(self) -> Result<Track, Error> {
+        check_range("tempo", self.tempo as u32, 32, 255)?;
+        check_range("speed", self.speed as u32, 1, 255)?;
+        check_range("global volume", self.global_volume as u32, 0, 128)?;
+        check_range("mix volume", self.mix_volume as u32, 0, 128)?;
+
+        for pan in self.pans.iter() {
+            if *pan < 128 && *pan != 100 {
+                check_range("pan", *pan as u32, 0, 64)?;
+            }
+        }
+
+        for (i, sample) in self.samples.iter().enumerate() {
+            // The player can't do anything useful with rates this low, see `Track::from_it`.
+            check_range("sample rate", sample.format.sample_rate.max(0) as u32, 4, i32::MAX as u32)?;
+
+            // A loop end of -1 means the loop runs to the end of the sample.
+            let (start, end) = (sample.loop_start, sample.loop_end);
+            let end_valid = end == -1 || (end >= start && end as usize <= sample.length());
+            if start < 0 || !end_valid {
+                return Err(Error::InvalidLoop { sample: i, start, end });
+            }
+        }
+
+        for (order, pattern) in self.orders.iter().enumerate() {
+            if *pattern < 254 && *pattern as usize >= self.patterns.len() {
+                return Err(Error::MissingPattern { order, pattern: *pattern });
+            }
+        }
+
+        for (p, pattern) in self.patterns.iter().enumerate() {
+            check_range("pattern length", pattern.rows as u32, 1, u16::MAX as u32)?;
+
+            for row in 0..pattern.rows {
+                for channel in 0..pattern.channels {
+                    let note = pattern.notes.get(channel as usize, row as usize);
+
+                    if let Some(sample) = note.sample {
+                        if sample as usize >= self.samples.len() {
+                            return Err(Error::MissingSample { pattern: p, row, channel, sample });
+                        }
+                    }
+
+                    if let Some(volume) = note.volume {
+                        // This is the whole IT volume column, so values above 64 are pans and volume effects.
+                        check_range("note volume", volume as u32, 0, 212)?;
+                    }
+                }
+            }
+        }
+
+        let num_channels = self.patterns.iter().map(|p| p.channels).max().unwrap_or(0);
+        let mut pans = self.pans;
+        if pans.len() < num_channels as usize {
+            pans.resize(num_channels as usize, 32);
+        }
+
+        let mut metadata = self.metadata;
+        metadata.samples.resize(self.samples.len(), SampleMetadata::default());
+
+        let length = track::calculate_length(&self.patterns, &self.orders, self.tempo, self.speed);
+
+        Ok(Track {
+            mod_type: self.mod_type,
+
+            patterns: self.patterns,
+            orders: self.orders,
+            samples: self.samples,
+
+            tempo: self.tempo,
+            speed: self.speed,
+
+            global_volume: self.global_volume,
+            pans,
+            mix_volume: self.mix_volume,
+
+            midi_macros: self.midi_macros,
+
+            metadata,
+
+            length_in_seconds: length.seconds,
+            loop_start: length.loop_start,
+            seek_table: length.seek_table
+        })
+    }
+}
+
+impl Default for TrackBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn check_range(name: &'static str, value: u32, min: u32, max: u32) -> Result<(), Error> {
+    if value < min || value > max {
+        return Err(Error::OutOfRange { name, value });
+    }
+
+    Ok(())
+}
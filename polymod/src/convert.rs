@@ -0,0 +1,203 @@
+use std::fmt;
+
+use mixr::FormatType;
+
+use crate::{track::{Track, Pattern}, Effect, Error, ModuleType, Note, PianoKey};
+
+/// Something that was lost or changed when converting a track to another format.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConversionWarning {
+    /// The effect doesn't exist in the new format, so it was removed.
+    UnsupportedEffect { pattern: usize, row: u16, channel: u16, effect: Effect },
+
+    /// The effect doesn't exist in the new format, so the closest one was used instead.
+    ApproximatedEffect { pattern: usize, row: u16, channel: u16, from: Effect, to: Effect },
+
+    /// The track has more channels than the new format allows, so the extra ones were removed.
+    TooManyChannels { channels: u16, max: u16 },
+
+    /// The pattern's length isn't allowed in the new format. Longer patterns are cut short, and shorter ones are
+    /// padded out and end with a pattern break where possible.
+    PatternResized { pattern: usize, rows: u16, new_rows: u16 },
+
+    /// A padded pattern needed a pattern break on its last original row, but no channel on that row had a free effect,
+    /// so the padding will be played.
+    BreakNotAdded { pattern: usize, row: u16 },
+
+    /// The sample's data was converted to something the new format supports.
+    SampleConverted { sample: usize, sixteen_bit: bool, channels: u8 }
+}
+
+impl fmt::Display for ConversionWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConversionWarning::UnsupportedEffect { pattern, row, channel, effect } =>
+                write!(f, "pattern {pattern}, row {row}, channel {}: {effect:?} is not supported and was removed", *channel as u32 + 1),
+            ConversionWarning::ApproximatedEffect { pattern, row, channel, from, to } =>
+                write!(f, "pattern {pattern}, row {row}, channel {}: {from:?} is not supported and was replaced with {to:?}", *channel as u32 + 1),
+            ConversionWarning::TooManyChannels { channels, max } =>
+                write!(f, "the track has {channels} channels but only {max} are supported, the rest were removed"),
+            ConversionWarning::PatternResized { pattern, rows, new_rows } =>
+                write!(f, "pattern {pattern} was resized from {rows} to {new_rows} rows"),
+            ConversionWarning::BreakNotAdded { pattern, row } =>
+                write!(f, "pattern {pattern}, row {row}: no channel had room for a pattern break, so the pattern is now longer"),
+            ConversionWarning::SampleConverted { sample, sixteen_bit, channels } =>
+                write!(f, "sample {} was converted to {}-bit {}", sample + 1, if *sixteen_bit { 16 } else { 8 }, if *channels == 1 { "mono" } else { "stereo" })
+        }
+    }
+}
+
+/// The result of converting a track, along with anything that didn't survive the conversion.
+pub struct Conversion {
+    pub track: Track,
+    pub warnings: Vec<ConversionWarning>
+}
+
+/// What each format is able to store. XM, S3M and MOD can't be loaded or saved yet, so for now converting to them only
+/// shows what would be lost.
+struct Capabilities {
+    max_channels: u16,
+    min_rows: u16,
+    max_rows: u16,
+    sixteen_bit: bool,
+    stereo: bool
+}
+
+fn capabilities(format: ModuleType) -> Capabilities {
+    match format {
+        ModuleType::PMM => Capabilities { max_channels: u16::MAX, min_rows: 1, max_rows: u16::MAX, sixteen_bit: true, stereo: true },
+        ModuleType::IT => Capabilities { max_channels: 64, min_rows: 1, max_rows: 200, sixteen_bit: true, stereo: true },
+        ModuleType::XM => Capabilities { max_channels: 32, min_rows: 1, max_rows: 256, sixteen_bit: true, stereo: false },
+        ModuleType::S3M => Capabilities { max_channels: 32, min_rows: 64, max_rows: 64, sixteen_bit: true, stereo: false },
+        ModuleType::MOD => Capabilities { max_channels: 32, min_rows: 64, max_rows: 64, sixteen_bit: false, stereo: false }
+    }
+}
+
+/// Get the effect to use in the given format. Returns the same effect if it is supported, a similar one if there is
+/// one, or None if it has to be removed.
+pub fn map_effect(effect: Effect, format: ModuleType) -> Option<Effect> {
+    let supported = match format {
+        ModuleType::PMM | ModuleType::IT => true,
+        ModuleType::XM => !matches!(effect, Effect::SetChannelVolume(_) | Effect::ChannelVolumeSlide(_) | Effect::FineVibrato(_)
+            | Effect::Panbrello(_) | Effect::MidiMacro(_)),
+        ModuleType::S3M => !matches!(effect, Effect::SetChannelVolume(_) | Effect::ChannelVolumeSlide(_) | Effect::PanningSlide(_)
+            | Effect::GlobalVolumeSlide(_) | Effect::Panbrello(_) | Effect::MidiMacro(_)),
+        ModuleType::MOD => !matches!(effect, Effect::Tremor(_) | Effect::SetChannelVolume(_) | Effect::ChannelVolumeSlide(_)
+            | Effect::PanningSlide(_) | Effect::Retrigger(_) | Effect::FineVibrato(_) | Effect::SetGlobalVolume(_)
+            | Effect::GlobalVolumeSlide(_) | Effect::Panbrello(_) | Effect::MidiMacro(_))
+    };
+
+    if supported {
+        return Some(effect);
+    }
+
+    match effect {
+        // Fine vibrato is the same as vibrato, only with a quarter of the depth.
+        Effect::FineVibrato(param) => Some(Effect::Vibrato((param & 0xF0) | ((param & 0xF) / 4).max(1))),
+        _ => None
+    }
+}
+
+/// Convert the track to the given format, changing anything the format can't store. Only IT can be saved at the
+/// moment, see [`save`].
+pub fn convert(track: &Track, format: ModuleType) -> Conversion {
+    let capabilities = capabilities(format);
+    let mut warnings = Vec::new();
+
+    let mut track = track.clone();
+    track.mod_type = format;
+
+    let num_channels = track.num_channels();
+    if num_channels > capabilities.max_channels {
+        warnings.push(ConversionWarning::TooManyChannels { channels: num_channels, max: capabilities.max_channels });
+    }
+
+    for (p, pattern) in track.patterns.iter_mut().enumerate() {
+        let channels = pattern.channels.min(capabilities.max_channels);
+        let rows = pattern.rows.clamp(capabilities.min_rows, capabilities.max_rows);
+
+        let mut new_pattern = Pattern::new(channels, rows);
+        for row in 0..rows.min(pattern.rows) {
+            for channel in 0..channels {
+                let mut note = *pattern.notes.get(channel as usize, row as usize);
+
+                match map_effect(note.effect, format) {
+                    Some(effect) if effect == note.effect => {},
+                    Some(effect) => {
+                        warnings.push(ConversionWarning::ApproximatedEffect { pattern: p, row, channel, from: note.effect, to: effect });
+                        note.effect = effect;
+                    },
+                    None => {
+                        warnings.push(ConversionWarning::UnsupportedEffect { pattern: p, row, channel, effect: note.effect });
+                        note.effect = Effect::None;
+                    }
+                }
+
+                new_pattern.set_note(channel, row, note);
+            }
+        }
+
+        if rows != pattern.rows {
+            warnings.push(ConversionWarning::PatternResized { pattern: p, rows: pattern.rows, new_rows: rows });
+
+            // Break out of the padding so the pattern still lasts as long as it did.
+            let last_row = pattern.rows.max(1) - 1;
+            let breaks = (0..channels).any(|c| matches!(new_pattern.notes.get(c as usize, last_row as usize).effect, Effect::PatternBreak(_) | Effect::PositionJump(_)));
+
+            if rows > pattern.rows && !breaks {
+                let free = (0..channels).find(|c| new_pattern.notes.get(*c as usize, last_row as usize).effect == Effect::None);
+
+                if let Some(channel) = free {
+                    let mut note = *new_pattern.notes.get(channel as usize, last_row as usize);
+                    if !note.initialized {
+                        note = Note::new(PianoKey::None, 0, None, None, Effect::None);
+                    }
+
+                    note.effect = Effect::PatternBreak(0);
+                    new_pattern.set_note(channel, last_row, note);
+                } else {
+                    warnings.push(ConversionWarning::BreakNotAdded { pattern: p, row: last_row });
+                }
+            }
+        }
+
+        *pattern = new_pattern;
+    }
+
+    track.pans.truncate(capabilities.max_channels as usize);
+
+    for (i, sample) in track.samples.iter_mut().enumerate() {
+        let sixteen_bit = sample.format.bytes_per_sample() >= 2 && capabilities.sixteen_bit;
+        let channels = sample.format.channels.clamp(1, if capabilities.stereo { 2 } else { 1 });
+
+        // Samples are only converted if they need to be, so nothing is lost for the ones that are fine already.
+        let is_native = matches!(sample.format.format_type, FormatType::I8 | FormatType::I16);
+        if is_native && sixteen_bit == (sample.format.bytes_per_sample() == 2) && channels == sample.format.channels {
+            continue;
+        }
+
+        *sample = sample.converted(sixteen_bit, channels);
+        warnings.push(ConversionWarning::SampleConverted { sample: i, sixteen_bit, channels });
+    }
+
+    track.update_length();
+
+    Conversion { track, warnings }
+}
+
+/// Load a track in the given format. Only IT is supported at the moment.
+pub fn load(data: &[u8], format: ModuleType) -> Result<Track, Error> {
+    match format {
+        ModuleType::IT => Track::from_it(data),
+        _ => Err(Error::Unsupported("formats other than IT"))
+    }
+}
+
+/// Save a track in the given format. The track should have been converted to that format first. Only IT is supported at
+/// the moment.
+pub fn save(track: &Track, format: ModuleType) -> Result<Vec<u8>, Error> {
+    match format {
+        ModuleType::IT => track.to_it(),
+        _ => Err(Error::Unsupported("formats other than IT"))
+    }
+}
@@ -0,0 +1,185 @@
+use crate::{track::{Track, Pattern, Selection}, Error, Note};
+
+/// A change to a track's patterns or orders that can be undone.
+#[derive(Clone)]
+pub enum Edit {
+    SetNote { pattern: usize, channel: u16, row: u16, note: Note },
+
+    InsertRows { pattern: usize, row: u16, count: u16 },
+    DeleteRows { pattern: usize, row: u16, count: u16 },
+    Resize { pattern: usize, channels: u16, rows: u16 },
+
+    /// Paste a block of notes, usually from [`Pattern::copy`], with its top left corner at the given position.
+    Paste { pattern: usize, channel: u16, row: u16, block: Pattern },
+    Transpose { pattern: usize, selection: Selection, semitones: i8 },
+    Clear { pattern: usize, selection: Selection },
+
+    /// Insert a pattern into the order list before the given index. The index can be the list's length, to add it at
+    /// the end.
+    InsertOrder { index: usize, pattern: u8 },
+    RemoveOrder { index: usize },
+    SetOrder { index: usize, pattern: u8 }
+}
+
+/// What an edit replaced, so it can be put back.
+enum Snapshot {
+    Pattern(usize, Pattern),
+    Orders(Vec<u8>)
+}
+
+/// Applies edits to a track, and keeps track of them so they can be undone and redone.
+/// The track's length is recalculated after every change.
+#[derive(Default)]
+pub struct History {
+    undo: Vec<(Edit, Snapshot)>,
+    redo: Vec<Edit>,
+
+    max_len: Option<usize>
+}
+
+impl History {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Only remember the given number of edits, forgetting the oldest ones first.
+    pub fn with_max_len(max_len: usize) -> Self {
+        Self { max_len: Some(max_len), ..Self::default() }
+    }
+
+    /// Apply an edit to the track. Nothing is changed if the edit fails.
+    pub fn apply(&mut self, track: &mut Track, edit: Edit) -> Result<(), Error> {
+        let snapshot = run(track, &edit)?;
+
+        self.undo.push((edit, snapshot));
+        self.redo.clear();
+
+        if let Some(max_len) = self.max_len {
+            if self.undo.len() > max_len {
+                self.undo.remove(0);
+            }
+        }
+
+        track.update_length();
+        Ok(())
+    }
+
+    /// Undo the last edit. Returns false if there was nothing to undo.
+    pub fn undo(&mut self, track: &mut Track) -> bool {
+        let Some((edit, snapshot)) = self.undo.pop() else {
+            return false;
+        };
+
+        match snapshot {
+            Snapshot::Pattern(index, pattern) => track.patterns[index] = pattern,
+            Snapshot::Orders(orders) => track.orders = orders
+        }
+
+        self.redo.push(edit);
+        track.update_length();
+
+        true
+    }
+
+    /// Redo the last edit that was undone. Returns false if there was nothing to redo.
+    pub fn redo(&mut self, track: &mut Track) -> Result<bool, Error> {
+        let Some(edit) = self.redo.pop() else {
+            return Ok(false);
+        };
+
+        // This can only fail if the track was changed outside of the history since the edit was undone.
+        let snapshot = match run(track, &edit) {
+            Ok(snapshot) => snapshot,
+            Err(err) => {
+                self.redo.push(edit);
+                return Err(err);
+            }
+        };
+
+        self.undo.push((edit, snapshot));
+        track.update_length();
+
+        Ok(true)
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.undo.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.redo.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.undo.clear();
+        self.redo.clear();
+    }
+}
+
+/// Run an edit, returning what it replaced.
+fn run(track: &mut Track, edit: &Edit) -> Result<Snapshot, Error> {
+    match edit {
+        Edit::SetNote { pattern, channel, row, note } => edit_pattern(track, *pattern, |p| p.try_set_note(*channel, *row, *note)),
+        Edit::InsertRows { pattern, row, count } => edit_pattern(track, *pattern, |p| p.insert_rows(*row, *count)),
+        Edit::DeleteRows { pattern, row, count } => edit_pattern(track, *pattern, |p| p.delete_rows(*row, *count)),
+        Edit::Resize { pattern, channels, rows } => edit_pattern(track, *pattern, |p| p.resize(*channels, *rows)),
+        Edit::Paste { pattern, channel, row, block } => edit_pattern(track, *pattern, |p| p.paste(*channel, *row, block)),
+        Edit::Transpose { pattern, selection, semitones } => edit_pattern(track, *pattern, |p| p.transpose(*selection, *semitones)),
+        Edit::Clear { pattern, selection } => edit_pattern(track, *pattern, |p| p.clear(*selection)),
+
+        Edit::InsertOrder { index, pattern } => {
+            if *index > track.orders.len() {
+                return Err(Error::OrderOutOfBounds(*index));
+            }
+
+            check_order(track, *index, *pattern)?;
+
+            let orders = track.orders.clone();
+            track.orders.insert(*index, *pattern);
+            Ok(Snapshot::Orders(orders))
+        },
+
+        Edit::RemoveOrder { index } => {
+            if *index >= track.orders.len() {
+                return Err(Error::OrderOutOfBounds(*index));
+            }
+
+            let orders = track.orders.clone();
+            track.orders.remove(*index);
+            Ok(Snapshot::Orders(orders))
+        },
+
+        Edit::SetOrder { index, pattern } => {
+            if *index >= track.orders.len() {
+                return Err(Error::OrderOutOfBounds(*index));
+            }
+
+            check_order(track, *index, *pattern)?;
+
+            let orders = track.orders.clone();
+            track.orders[*index] = *pattern;
+            Ok(Snapshot::Orders(orders))
+        }
+    }
+}
+
+/// Edit a copy of the pattern, so a failed edit leaves it alone.
+fn edit_pattern(track: &mut Track, index: usize, edit: impl FnOnce(&mut Pattern) -> Result<(), Error>) -> Result<Snapshot, Error> {
+    let Some(pattern) = track.patterns.get_mut(index) else {
+        return Err(Error::OutOfRange { name: "pattern", value: index as u32 });
+    };
+
+    let mut edited = pattern.clone();
+    edit(&mut edited)?;
+
+    Ok(Snapshot::Pattern(index, std::mem::replace(pattern, edited)))
+}
+
+/// Orders can point at any pattern that exists, or be a skip (254) or end (255) marker.
+fn check_order(track: &Track, order: usize, pattern: u8) -> Result<(), Error> {
+    if pattern < 254 && pattern as usize >= track.patterns.len() {
+        return Err(Error::MissingPattern { order, pattern });
+    }
+
+    Ok(())
+}
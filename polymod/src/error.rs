@@ -0,0 +1,59 @@
+use std::fmt;
+
+/// An error from loading or building a track.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Error {
+    /// The file ended before everything could be read.
+    TruncatedFile,
+
+    /// The file didn't contain the expected identifier, so it is most likely not the format it was loaded as.
+    BadMagic { expected: &'static str },
+
+    /// The file uses a feature that isn't supported yet.
+    Unsupported(&'static str),
+
+    /// An offset in the file points outside of it.
+    InvalidOffset(usize),
+
+    /// A value that doesn't correspond to any note.
+    InvalidNote(u8),
+
+    /// An order refers to a pattern that doesn't exist.
+    MissingPattern { order: usize, pattern: u8 },
+
+    /// A note refers to a sample that doesn't exist.
+    MissingSample { pattern: usize, row: u16, channel: u16, sample: u8 },
+
+    /// A value is outside of the range allowed for it.
+    OutOfRange { name: &'static str, value: u32 },
+
+    /// A sample's loop points are backwards or outside of the sample.
+    InvalidLoop { sample: usize, start: i32, end: i32 },
+
+    /// A position is outside of the pattern.
+    OutOfBounds { channel: u16, row: u16 },
+
+    /// An index is past the end of the order list.
+    OrderOutOfBounds(usize)
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::TruncatedFile => write!(f, "the file ended unexpectedly"),
+            Error::BadMagic { expected } => write!(f, "expected \"{expected}\", not found"),
+            Error::Unsupported(feature) => write!(f, "{feature} are not currently supported"),
+            Error::InvalidOffset(offset) => write!(f, "offset {offset:#X} is outside of the file"),
+            Error::InvalidNote(note) => write!(f, "{note} is not a valid note"),
+            Error::MissingPattern { order, pattern } => write!(f, "order {order} refers to pattern {pattern}, which doesn't exist"),
+            Error::MissingSample { pattern, row, channel, sample } =>
+                write!(f, "pattern {pattern}, row {row}, channel {} refers to sample {sample}, which doesn't exist", *channel as u32 + 1),
+            Error::OutOfRange { name, value } => write!(f, "{value} is not a valid {name}"),
+            Error::InvalidLoop { sample, start, end } => write!(f, "sample {sample} has an invalid loop from {start} to {end}"),
+            Error::OutOfBounds { channel, row } => write!(f, "channel {}, row {row} is outside of the pattern", *channel as u32 + 1),
+            Error::OrderOutOfBounds(index) => write!(f, "order {index} is past the end of the order list")
+        }
+    }
+}
+
+impl std::error::Error for Error {}
@@ -0,0 +1,39 @@
+use crate::{PianoKey, Effect};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PlayerEvent {
+    RowChanged { order: usize, row: usize },
+    OrderChanged { order: usize, pattern: u8 },
+
+    NoteTriggered { channel: u16, sample: u8, key: PianoKey, octave: u8, volume: u8 },
+
+    /// An effect was read on the first tick of a row. Effects that run on every tick are only reported once.
+    EffectExecuted { channel: u16, effect: Effect },
+
+    SongLooped { count: u32 }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TimedEvent {
+    /// The frame the event happened on, counted from the start of the track. This lines up with
+    /// [`TrackPlayer::elapsed_seconds`](crate::track_player::TrackPlayer::elapsed_seconds).
+    pub frame: u64,
+
+    pub event: PlayerEvent
+}
+
+/// Holds events until they are drained. Nothing is stored unless it's enabled, so it can't grow forever if nobody is
+/// listening.
+#[derive(Default)]
+pub(crate) struct EventQueue {
+    pub enabled: bool,
+    pub events: Vec<TimedEvent>
+}
+
+impl EventQueue {
+    pub fn push(&mut self, frame: u64, event: PlayerEvent) {
+        if self.enabled {
+            self.events.push(TimedEvent { frame, event });
+        }
+    }
+}
@@ -0,0 +1,67 @@
+use std::sync::mpsc::Sender;
+
+/// A command sent to a [`TrackPlayer`](crate::track_player::TrackPlayer) through a [`PlayerHandle`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PlayerCommand {
+    SeekSeconds(f64),
+    SeekPosition(usize, usize),
+
+    SetPaused(bool),
+    Stop,
+
+    SetPitchTuning(f64),
+    SetTempoTuning(f64),
+
+    SetChannelMuted(u16, bool),
+    SetChannelSolo(u16, bool)
+}
+
+/// Controls a player from another thread, for example while the player lives inside an audio callback.
+/// Commands are queued without locking, and the player applies them at the start of the next tick.
+/// If the player no longer exists, commands are silently dropped.
+#[derive(Clone)]
+pub struct PlayerHandle {
+    sender: Sender<PlayerCommand>
+}
+
+impl PlayerHandle {
+    pub(crate) fn new(sender: Sender<PlayerCommand>) -> Self {
+        Self { sender }
+    }
+
+    pub fn send(&self, command: PlayerCommand) {
+        let _ = self.sender.send(command);
+    }
+
+    pub fn seek_seconds(&self, seconds: f64) {
+        self.send(PlayerCommand::SeekSeconds(seconds));
+    }
+
+    pub fn seek_position(&self, order: usize, row: usize) {
+        self.send(PlayerCommand::SeekPosition(order, row));
+    }
+
+    pub fn set_paused(&self, paused: bool) {
+        self.send(PlayerCommand::SetPaused(paused));
+    }
+
+    pub fn stop(&self) {
+        self.send(PlayerCommand::Stop);
+    }
+
+    pub fn set_pitch_tuning(&self, tuning: f64) {
+        self.send(PlayerCommand::SetPitchTuning(tuning));
+    }
+
+    pub fn set_tempo_tuning(&self, tuning: f64) {
+        self.send(PlayerCommand::SetTempoTuning(tuning));
+    }
+
+    pub fn set_channel_muted(&self, channel: u16, muted: bool) {
+        self.send(PlayerCommand::SetChannelMuted(channel, muted));
+    }
+
+    pub fn set_channel_solo(&self, channel: u16, solo: bool) {
+        self.send(PlayerCommand::SetChannelSolo(channel, solo));
+    }
+}
@@ -2,7 +2,18 @@ pub mod track;
 pub mod sample;
 pub mod track_player;
 pub mod utils;
+pub mod midi;
+pub mod event;
+pub mod handle;
+pub mod error;
+pub mod convert;
+pub mod builder;
+pub mod edit;
+mod mixer;
 
+pub use error::Error;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum ModuleType {
     PMM,
     IT,
@@ -11,6 +22,30 @@ pub enum ModuleType {
     MOD
 }
 
+impl ModuleType {
+    /// Get the format from a file extension, ignoring case.
+    pub fn from_extension(extension: &str) -> Option<ModuleType> {
+        match extension.to_ascii_lowercase().as_str() {
+            "pmm" => Some(ModuleType::PMM),
+            "it" => Some(ModuleType::IT),
+            "xm" => Some(ModuleType::XM),
+            "s3m" => Some(ModuleType::S3M),
+            "mod" => Some(ModuleType::MOD),
+            _ => None
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ModuleType::PMM => "Polymod",
+            ModuleType::IT => "Impulse Tracker",
+            ModuleType::XM => "FastTracker 2",
+            ModuleType::S3M => "Scream Tracker 3",
+            ModuleType::MOD => "ProTracker"
+        }
+    }
+}
+
 #[derive(PartialEq, Copy, Clone, Debug)]
 pub enum PianoKey {
     None,
@@ -32,6 +67,38 @@ pub enum PianoKey {
     B
 }
 
+impl PianoKey {
+    const KEYS: [PianoKey; 12] = [
+        PianoKey::C, PianoKey::CSharp, PianoKey::D, PianoKey::DSharp, PianoKey::E, PianoKey::F,
+        PianoKey::FSharp, PianoKey::G, PianoKey::GSharp, PianoKey::A, PianoKey::ASharp, PianoKey::B
+    ];
+
+    /// Get the key the given number of semitones above C, from 0-11.
+    pub fn from_semitone(semitone: u8) -> Option<PianoKey> {
+        Self::KEYS.get(semitone as usize).copied()
+    }
+
+    /// Get the number of semitones above C, or None if this isn't a key that can be played.
+    pub fn semitone(self) -> Option<u8> {
+        Self::KEYS.iter().position(|key| *key == self).map(|semitone| semitone as u8)
+    }
+}
+
+/// Converts back from `key as u8`.
+impl TryFrom<u8> for PianoKey {
+    type Error = Error;
+
+    fn try_from(value: u8) -> Result<Self, Error> {
+        match value {
+            0 => Ok(PianoKey::None),
+            1 => Ok(PianoKey::NoteCut),
+            2 => Ok(PianoKey::NoteOff),
+            3 => Ok(PianoKey::NoteFade),
+            _ => PianoKey::from_semitone(value - PianoKey::C as u8).ok_or(Error::InvalidNote(value))
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Effect {
     None,
@@ -64,7 +131,7 @@ pub enum Effect {
     MidiMacro(u8) // Zxx
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct Note {
     pub initialized: bool,
 
@@ -93,8 +160,29 @@ impl Note {
             effect
         }
     }
+
+    /// Create a note from a MIDI note number, where 60 is middle C (C-5). Returns None if the number is above 127.
+    pub fn from_midi(number: u8, sample: Option<u8>, volume: Option<u8>, effect: Effect) -> Option<Self> {
+        if number > 127 {
+            return None;
+        }
+
+        let key = PianoKey::from_semitone(number % 12)?;
+        Some(Self::new(key, number / 12, sample, volume, effect))
+    }
+
+    /// Get the MIDI note number of this note, or None if it has no key or is out of MIDI's range.
+    pub fn to_midi(&self) -> Option<u8> {
+        let number = self.octave as u32 * 12 + self.key.semitone()? as u32;
+        if number > 127 {
+            return None;
+        }
+
+        Some(number as u8)
+    }
 }
 
+#[derive(Clone)]
 pub struct Arr2D<T: Default> {
     vec: Vec<T>,
     columns: usize,
@@ -126,14 +214,15 @@ impl<T: Default> Arr2D<T> {
     pub fn rows(&self) -> usize {
         self.rows
     }
-}
 
-#[inline(always)]
-#[cfg(debug_assertions)]
-pub fn log(text: String) {
-    println!("{text}");
-}
+    /// Iterate over each row, as a slice of its columns.
+    pub fn iter_rows(&self) -> impl Iterator<Item = &[T]> {
+        (0..self.rows).map(move |row| &self.vec[row * self.columns..(row + 1) * self.columns])
+    }
 
-#[inline(always)]
-#[cfg(not(debug_assertions))]
-pub fn log(text: String) {}
\ No newline at end of file
+    /// Iterate down the given column. Nothing is returned if the column is out of range.
+    pub fn iter_column(&self, column: usize) -> impl Iterator<Item = &T> {
+        let rows = if column < self.columns { self.rows } else { 0 };
+        (0..rows).map(move |row| &self.vec[row * self.columns + column])
+    }
+}
\ No newline at end of file
@@ -0,0 +1,148 @@
+/// Size in bytes of a single macro string in the embedded MIDI configuration.
+const MACRO_LENGTH: usize = 32;
+
+/// Size in bytes of the entire embedded MIDI configuration block.
+pub const MIDI_CONFIG_LENGTH: usize = MACRO_LENGTH * (9 + 16 + 128);
+
+/// The MIDI macro table used to evaluate Zxx (and SFx) commands.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MidiMacros {
+    /// Global macros (start, stop, tick, note on, note off, volume, pan, bank change, program change).
+    pub global: Vec<String>,
+
+    /// The SF0-SFF parametered macros, selected by SFx and executed with Z00-Z7F.
+    pub parametered: Vec<String>,
+
+    /// The Z80-ZFF fixed macros.
+    pub fixed: Vec<String>
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MacroCommand {
+    FilterCutoff(u8),
+    FilterResonance(u8)
+}
+
+impl Default for MidiMacros {
+    fn default() -> Self {
+        Self::it_default()
+    }
+}
+
+impl MidiMacros {
+    /// The default table Impulse Tracker uses when no configuration is embedded in the file.
+    pub fn it_default() -> Self {
+        let global = ["FF", "FC", "", "9c n v", "9c n 0", "", "", "", "Cc p"].iter().map(|m| m.to_string()).collect();
+
+        let mut parametered = vec![String::new(); 16];
+        parametered[0] = String::from("F0F000z");
+
+        // Z80-Z8F set the resonance in steps of 8, the rest are unused.
+        let mut fixed = vec![String::new(); 128];
+        for (i, text) in fixed.iter_mut().take(16).enumerate() {
+            *text = format!("F0F001{:02X}", i * 8);
+        }
+
+        Self { global, parametered, fixed }
+    }
+
+    /// Read an embedded MIDI configuration block. Each macro is a null-terminated 32 byte string.
+    pub fn from_bytes(data: &[u8]) -> Self {
+        let mut macros = data.chunks(MACRO_LENGTH).map(|chunk| {
+            let end = chunk.iter().position(|c| *c == 0).unwrap_or(chunk.len());
+            String::from_utf8_lossy(&chunk[..end]).to_string()
+        });
+
+        let global = macros.by_ref().take(9).collect();
+        let parametered = macros.by_ref().take(16).collect();
+        let fixed = macros.take(128).collect();
+
+        Self { global, parametered, fixed }
+    }
+
+    /// Write the macros out as an embedded MIDI configuration block. Macros that don't fit are cut short.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut data = vec![0; MIDI_CONFIG_LENGTH];
+
+        let slots = self.global.iter().take(9).enumerate()
+            .chain(self.parametered.iter().take(16).enumerate().map(|(i, m)| (9 + i, m)))
+            .chain(self.fixed.iter().take(128).enumerate().map(|(i, m)| (9 + 16 + i, m)));
+
+        for (slot, text) in slots {
+            // Leave room for the null terminator.
+            let length = text.len().min(MACRO_LENGTH - 1);
+            data[slot * MACRO_LENGTH..slot * MACRO_LENGTH + length].copy_from_slice(&text.as_bytes()[..length]);
+        }
+
+        data
+    }
+
+    /// Get the macro executed by a Zxx command, given the parametered macro currently selected with SFx.
+    pub fn zxx_macro(&self, active_macro: u8, param: u8) -> &str {
+        let midi_macro = if param < 0x80 {
+            self.parametered.get(active_macro as usize)
+        } else {
+            self.fixed.get((param - 0x80) as usize)
+        };
+
+        midi_macro.map(|m| m.as_str()).unwrap_or("")
+    }
+}
+
+/// Evaluate a macro string, returning the commands it contains that the player understands.
+/// Only the internal filter messages (F0F000xx and F0F001xx) are supported, as polymod has no MIDI output.
+pub fn evaluate_macro(midi_macro: &str, param: u8) -> Vec<MacroCommand> {
+    let mut bytes = Vec::new();
+    let mut nibble: Option<u8> = None;
+
+    for c in midi_macro.chars() {
+        let value = match c {
+            '0'..='9' | 'A'..='F' => {
+                let digit = c.to_digit(16).unwrap() as u8;
+                match nibble.take() {
+                    Some(high) => bytes.push((high << 4) | digit),
+                    None => nibble = Some(digit)
+                }
+                continue;
+            },
+            'z' => param & 0x7F,
+            // Any other variable relates to MIDI output so we just treat it as 0.
+            'a'..='y' => 0,
+            _ => continue
+        };
+
+        if let Some(high) = nibble.take() {
+            bytes.push(high);
+        }
+
+        bytes.push(value);
+    }
+
+    if let Some(high) = nibble {
+        bytes.push(high);
+    }
+
+    let mut commands = Vec::new();
+    let mut i = 0;
+    while i + 4 <= bytes.len() {
+        if bytes[i] != 0xF0 || bytes[i + 1] != 0xF0 {
+            i += 1;
+            continue;
+        }
+
+        match bytes[i + 2] {
+            0 => commands.push(MacroCommand::FilterCutoff(bytes[i + 3] & 0x7F)),
+            1 => commands.push(MacroCommand::FilterResonance(bytes[i + 3] & 0x7F)),
+            _ => {}
+        }
+
+        i += 4;
+    }
+
+    commands
+}
+
+/// Convert an IT filter cutoff (0-127) to a frequency in Hz.
+pub fn cutoff_to_frequency(cutoff: u8) -> f64 {
+    110.0 * f64::powf(2.0, 0.25 + cutoff as f64 / 24.0)
+}
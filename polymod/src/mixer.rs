@@ -0,0 +1,94 @@
+use mixr::{system::AudioSystem, BufferDescription, ChannelProperties};
+
+/// Wraps the mixr audio system, and optionally an extra single-voice system per stem.
+/// Everything that happens to a voice that has a stem is mirrored to that stem's system, so each stem ends up with
+/// exactly what that voice contributes to the main mix.
+pub(crate) struct Mixer {
+    system: AudioSystem,
+    stems: Vec<AudioSystem>,
+    stem_values: Vec<f64>,
+
+    // Each system has its own ID for the same buffer, so the index into this is used instead.
+    buffers: Vec<Vec<i32>>
+}
+
+impl Mixer {
+    pub fn new(sample_rate: i32, voices: u16, stems: usize) -> Self {
+        let stems = (0..stems.min(voices as usize)).map(|_| AudioSystem::new(sample_rate, 1)).collect::<Vec<_>>();
+
+        Self {
+            system: AudioSystem::new(sample_rate, voices),
+            stem_values: vec![0.0; stems.len()],
+            stems,
+
+            buffers: Vec::new()
+        }
+    }
+
+    pub fn create_buffer(&mut self, description: BufferDescription, data: &[u8]) -> usize {
+        let mut ids = Vec::with_capacity(1 + self.stems.len());
+        ids.push(self.system.create_buffer(description, Some(data)));
+
+        for stem in self.stems.iter_mut() {
+            ids.push(stem.create_buffer(description, Some(data)));
+        }
+
+        self.buffers.push(ids);
+        self.buffers.len() - 1
+    }
+
+    pub fn play_buffer(&mut self, buffer: usize, voice: u16, properties: ChannelProperties) {
+        let ids = &self.buffers[buffer];
+        self.system.play_buffer(ids[0], voice, properties).unwrap();
+
+        if let Some(stem) = self.stems.get_mut(voice as usize) {
+            stem.play_buffer(ids[voice as usize + 1], 0, properties).unwrap();
+        }
+    }
+
+    pub fn set_channel_properties(&mut self, voice: u16, properties: ChannelProperties) {
+        let _ = self.system.set_channel_properties(voice, properties);
+
+        if let Some(stem) = self.stems.get_mut(voice as usize) {
+            let _ = stem.set_channel_properties(0, properties);
+        }
+    }
+
+    pub fn stop(&mut self, voice: u16) {
+        let _ = self.system.stop(voice);
+
+        if let Some(stem) = self.stems.get_mut(voice as usize) {
+            let _ = stem.stop(0);
+        }
+    }
+
+    pub fn seek_to_sample(&mut self, voice: u16, sample: usize) {
+        let _ = self.system.seek_to_sample(voice, sample);
+
+        if let Some(stem) = self.stems.get_mut(voice as usize) {
+            let _ = stem.seek_to_sample(0, sample);
+        }
+    }
+
+    pub fn num_voices(&self) -> u16 {
+        self.system.num_channels()
+    }
+
+    pub fn num_stems(&self) -> usize {
+        self.stems.len()
+    }
+
+    /// Advance the main mix and every stem by a half sample, returning the main mix.
+    pub fn advance(&mut self) -> f64 {
+        for (stem, value) in self.stems.iter_mut().zip(self.stem_values.iter_mut()) {
+            *value = stem.advance();
+        }
+
+        self.system.advance()
+    }
+
+    /// Get the value of the given stem from the last call to [`Mixer::advance`].
+    pub fn stem_value(&self, stem: usize) -> f64 {
+        self.stem_values[stem]
+    }
+}
@@ -1,5 +1,8 @@
-use mixr::{AudioFormat, FormatType};
+use mixr::{system::AudioSystem, AudioFormat, BufferDescription, ChannelProperties, DataType, FormatType};
 
+use crate::PianoKey;
+
+#[derive(Clone)]
 pub struct Sample {
     pub data: Vec<u8>,
     pub format: AudioFormat,
@@ -32,6 +35,185 @@ impl Sample {
             default_volume
         }
     }
+
+    /// Get the number of frames (samples per channel) in this sample.
+    pub fn length(&self) -> usize {
+        self.data.len() / (self.format.channels as usize * self.format.bytes_per_sample() as usize).max(1)
+    }
+
+    /// Run the sample through an IT-style resonant low pass filter, returning 16-bit data in the same
+    /// channel layout. The cutoff is given in Hz at the sample's own sample rate.
+    pub fn filtered(&self, cutoff: f64, resonance: u8) -> Vec<u8> {
+        let sample_rate = self.format.sample_rate as f64;
+        // Very low sample rates would otherwise put the upper limit below the lower one.
+        let cutoff = cutoff.clamp(1.0, (sample_rate * 0.5 - 1.0).max(1.0));
+
+        // These are the same coefficients Impulse Tracker uses for its filters.
+        let damping = f64::powf(10.0, -(resonance as f64) * ((24.0 / 128.0) / 20.0));
+        let fc = cutoff * 2.0 * std::f64::consts::PI / sample_rate;
+        let d = ((1.0 - 2.0 * damping) * fc).min(2.0);
+        let d = (2.0 * damping - d) / fc;
+        let e = f64::powf(1.0 / fc, 2.0);
+
+        let gain = 1.0 / (1.0 + d + e);
+        let fb0 = (d + e + e) / (1.0 + d + e);
+        let fb1 = -e / (1.0 + d + e);
+
+        let channels = self.format.channels.max(1) as usize;
+        let mut history = vec![(0.0, 0.0); channels];
+
+        let mut data = Vec::with_capacity(self.length() * channels * 2);
+        for frame in 0..self.length() {
+            for (c, (y1, y2)) in history.iter_mut().enumerate() {
+                let input = self.get_value(frame * channels + c);
+                let output = (gain * input + fb0 * *y1 + fb1 * *y2).clamp(-1.0, 1.0);
+
+                *y2 = *y1;
+                *y1 = output;
+
+                data.extend_from_slice(&((output * i16::MAX as f64) as i16).to_le_bytes());
+            }
+        }
+
+        data
+    }
+
+    /// Get a copy of the sample as signed 8 or 16-bit data with the given number of channels. Stereo samples are
+    /// mixed down to mono by averaging both channels, and mono samples are copied to both channels to make stereo.
+    pub fn converted(&self, sixteen_bit: bool, channels: u8) -> Sample {
+        let source_channels = self.format.channels.max(1) as usize;
+        let channels = channels.clamp(1, 2);
+
+        let mut data = Vec::with_capacity(self.length() * channels as usize * if sixteen_bit { 2 } else { 1 });
+        for frame in 0..self.length() {
+            let values = (0..source_channels).map(|c| self.get_value(frame * source_channels + c)).collect::<Vec<_>>();
+            let mono = values.iter().sum::<f64>() / values.len() as f64;
+
+            for c in 0..channels as usize {
+                let value = if channels == 1 { mono } else { values[c.min(values.len() - 1)] };
+
+                if sixteen_bit {
+                    data.extend_from_slice(&((value * 32768.0).clamp(-32768.0, 32767.0) as i16).to_le_bytes());
+                } else {
+                    data.push((value * 128.0).clamp(-128.0, 127.0) as i8 as u8);
+                }
+            }
+        }
+
+        let mut format = self.format;
+        format.format_type = if sixteen_bit { FormatType::I16 } else { FormatType::I8 };
+        format.channels = channels;
+
+        Sample { data, format, ..self.clone() }
+    }
+
+    /// Get the value of the sample at the given index, in the range of -1.0 to 1.0.
+    pub(crate) fn get_value(&self, index: usize) -> f64 {
+        match self.format.format_type {
+            FormatType::U8 => (self.data[index] as f64 - 128.0) / 128.0,
+            FormatType::I8 => self.data[index] as i8 as f64 / 128.0,
+            FormatType::I16 => i16::from_le_bytes([self.data[index * 2], self.data[index * 2 + 1]]) as f64 / 32768.0,
+            _ => 0.0
+        }
+    }
+}
+
+/// Plays a single sample on its own, without needing a track. Useful for previewing samples.
+pub struct SamplePlayer {
+    system: AudioSystem,
+    buffer: i32,
+    properties: ChannelProperties,
+
+    sample_rate: i32,
+    sample_rate_ratio: f64,
+    multiplier: f64,
+    global_volume: u8,
+    length: usize,
+
+    playing: bool,
+    position: f64
+}
+
+impl SamplePlayer {
+    pub fn new(sample: &Sample, sample_rate: i32) -> Self {
+        let mut system = AudioSystem::new(sample_rate, 1);
+        let buffer = system.create_buffer(BufferDescription { data_type: DataType::Pcm, format: sample.format }, Some(&sample.data));
+
+        let properties = ChannelProperties {
+            looping: sample.looping,
+            loop_start: sample.loop_start,
+            loop_end: sample.loop_end,
+            ..Default::default()
+        };
+
+        Self {
+            system,
+            buffer,
+            properties,
+
+            sample_rate,
+            sample_rate_ratio: sample.format.sample_rate as f64 / sample_rate as f64,
+            multiplier: sample.multiplier,
+            global_volume: sample.global_volume,
+            length: sample.length(),
+
+            playing: false,
+            position: 0.0
+        }
+    }
+
+    /// Start playing the sample from the beginning at the given note. Volume is from 0-64.
+    pub fn play(&mut self, key: PianoKey, octave: u8, volume: u8) {
+        self.properties.volume = (volume.min(64) as f64 / 64.0) * (self.global_volume.min(64) as f64 / 64.0);
+        self.properties.speed = crate::track_player::calculate_speed(key, octave, self.multiplier);
+
+        self.system.play_buffer(self.buffer, 0, self.properties).unwrap();
+        self.playing = true;
+        self.position = 0.0;
+    }
+
+    pub fn stop(&mut self) {
+        let _ = self.system.stop(0);
+        self.playing = false;
+    }
+
+    /// Whether the sample is still playing. Looping samples play until they are stopped.
+    pub fn is_playing(&self) -> bool {
+        self.playing
+    }
+
+    pub fn set_interpolation(&mut self, interpolation: mixr::InterpolationType) {
+        self.properties.interpolation = interpolation;
+        let _ = self.system.set_channel_properties(0, self.properties);
+    }
+
+    pub fn sample_rate(&self) -> i32 {
+        self.sample_rate
+    }
+
+    /// Render interleaved stereo audio into `out`, returning the number of frames written. This is less than the
+    /// length of `out` if the sample finishes.
+    pub fn render(&mut self, out: &mut [f32]) -> usize {
+        let mut frames = 0;
+
+        for frame in out.chunks_exact_mut(2) {
+            if !self.playing {
+                break;
+            }
+
+            frame[0] = self.system.advance() as f32;
+            frame[1] = self.system.advance() as f32;
+            frames += 1;
+
+            // mixr doesn't tell us when a voice has finished, so keep track of where it is ourselves.
+            self.position += self.properties.speed * self.sample_rate_ratio;
+            if !self.properties.looping && self.position >= self.length as f64 {
+                self.stop();
+            }
+        }
+
+        frames
+    }
 }
 
 fn fix_sample(data: &mut Vec<u8>, format: &AudioFormat) {
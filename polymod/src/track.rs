@@ -1,13 +1,15 @@
 use mixr::{AudioFormat, FormatType};
 
-use crate::Effect;
+use crate::{Effect, Error};
+use crate::utils::binary::{BinaryReader, BinaryWriter};
+use crate::midi::{MidiMacros, MIDI_CONFIG_LENGTH};
 
 use super::{PianoKey, ModuleType};
 
 use super::{Arr2D, Note, sample::Sample};
-use std::collections::HashMap;
-use std::io;
+use std::collections::HashSet;
 
+#[derive(Clone)]
 pub struct Pattern {
     pub notes: Arr2D<Note>,
     pub channels: u16,
@@ -19,11 +21,206 @@ impl Pattern {
         Self { notes: Arr2D::new(channels as usize, rows as usize), channels, rows }
     }
 
+    /// Set a note. Panics if the position is outside of the pattern, see [`Pattern::try_set_note`] for a checked version.
     pub fn set_note(&mut self, channel: u16, row: u16, note: Note) {
         self.notes.set(channel as usize, row as usize, note);
     }
+
+    /// Get a note, or None if the position is outside of the pattern.
+    pub fn note(&self, channel: u16, row: u16) -> Option<&Note> {
+        if channel >= self.channels || row >= self.rows {
+            return None;
+        }
+
+        Some(self.notes.get(channel as usize, row as usize))
+    }
+
+    /// Iterate over each row, as a slice of the notes in each channel.
+    pub fn iter_rows(&self) -> impl Iterator<Item = &[Note]> {
+        self.notes.iter_rows()
+    }
+
+    /// Iterate down the given channel, one note per row. Nothing is returned if the channel is outside of the pattern.
+    pub fn iter_channel(&self, channel: u16) -> impl Iterator<Item = &Note> {
+        self.notes.iter_column(channel as usize)
+    }
+
+    pub fn try_set_note(&mut self, channel: u16, row: u16, note: Note) -> Result<(), Error> {
+        self.check_bounds(channel, row)?;
+        self.set_note(channel, row, note);
+
+        Ok(())
+    }
+
+    /// Insert empty rows before the given row, making the pattern longer. The row can be the pattern's length, to add
+    /// them at the end.
+    pub fn insert_rows(&mut self, row: u16, count: u16) -> Result<(), Error> {
+        if row > self.rows {
+            return Err(Error::OutOfBounds { channel: 0, row });
+        }
+
+        let rows = self.rows.checked_add(count).ok_or(Error::OutOfRange { name: "pattern length", value: self.rows as u32 + count as u32 })?;
+        let mut pattern = Pattern::new(self.channels, rows);
+
+        for r in 0..self.rows {
+            let new_row = if r < row { r } else { r + count };
+            for c in 0..self.channels {
+                pattern.set_note(c, new_row, *self.notes.get(c as usize, r as usize));
+            }
+        }
+
+        *self = pattern;
+        Ok(())
+    }
+
+    /// Remove rows starting at the given one, making the pattern shorter. A pattern always keeps at least one row.
+    pub fn delete_rows(&mut self, row: u16, count: u16) -> Result<(), Error> {
+        let end = row as u32 + count as u32;
+        if end > self.rows as u32 {
+            return Err(Error::OutOfBounds { channel: 0, row: end.min(u16::MAX as u32) as u16 });
+        }
+
+        if count >= self.rows {
+            return Err(Error::OutOfRange { name: "pattern length", value: 0 });
+        }
+
+        let mut pattern = Pattern::new(self.channels, self.rows - count);
+        for r in (0..self.rows).filter(|r| *r < row || *r >= row + count) {
+            let new_row = if r < row { r } else { r - count };
+            for c in 0..self.channels {
+                pattern.set_note(c, new_row, *self.notes.get(c as usize, r as usize));
+            }
+        }
+
+        *self = pattern;
+        Ok(())
+    }
+
+    /// Change the size of the pattern. Any notes that no longer fit are removed.
+    pub fn resize(&mut self, channels: u16, rows: u16) -> Result<(), Error> {
+        if channels == 0 {
+            return Err(Error::OutOfRange { name: "number of channels", value: 0 });
+        }
+
+        if rows == 0 {
+            return Err(Error::OutOfRange { name: "pattern length", value: 0 });
+        }
+
+        let mut pattern = Pattern::new(channels, rows);
+        for r in 0..rows.min(self.rows) {
+            for c in 0..channels.min(self.channels) {
+                pattern.set_note(c, r, *self.notes.get(c as usize, r as usize));
+            }
+        }
+
+        *self = pattern;
+        Ok(())
+    }
+
+    /// Copy the selected notes into a new pattern the size of the selection.
+    pub fn copy(&self, selection: Selection) -> Result<Pattern, Error> {
+        self.check_selection(selection)?;
+
+        let mut block = Pattern::new(selection.channels, selection.rows);
+        for r in 0..selection.rows {
+            for c in 0..selection.channels {
+                block.set_note(c, r, *self.notes.get((selection.channel + c) as usize, (selection.row + r) as usize));
+            }
+        }
+
+        Ok(block)
+    }
+
+    /// Paste a block of notes with its top left corner at the given position. Anything that goes past the edge of the
+    /// pattern is cut off.
+    pub fn paste(&mut self, channel: u16, row: u16, block: &Pattern) -> Result<(), Error> {
+        self.check_bounds(channel, row)?;
+
+        for r in 0..block.rows.min(self.rows - row) {
+            for c in 0..block.channels.min(self.channels - channel) {
+                self.set_note(channel + c, row + r, *block.notes.get(c as usize, r as usize));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Move the selected notes up or down by the given number of semitones. Notes that would end up outside of C-0 to
+    /// B-9 are left as they are.
+    pub fn transpose(&mut self, selection: Selection, semitones: i8) -> Result<(), Error> {
+        self.check_selection(selection)?;
+
+        for r in selection.row..selection.row + selection.rows {
+            for c in selection.channel..selection.channel + selection.channels {
+                let mut note = *self.notes.get(c as usize, r as usize);
+                let Some(semitone) = note.key.semitone() else {
+                    continue;
+                };
+
+                let number = note.octave as i32 * 12 + semitone as i32 + semitones as i32;
+                if !(0..120).contains(&number) {
+                    continue;
+                }
+
+                note.key = PianoKey::from_semitone((number % 12) as u8).unwrap();
+                note.octave = (number / 12) as u8;
+                self.set_note(c, r, note);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Remove all of the selected notes.
+    pub fn clear(&mut self, selection: Selection) -> Result<(), Error> {
+        self.check_selection(selection)?;
+
+        for r in selection.row..selection.row + selection.rows {
+            for c in selection.channel..selection.channel + selection.channels {
+                self.set_note(c, r, Note::default());
+            }
+        }
+
+        Ok(())
+    }
+
+    fn check_bounds(&self, channel: u16, row: u16) -> Result<(), Error> {
+        if channel >= self.channels || row >= self.rows {
+            return Err(Error::OutOfBounds { channel, row });
+        }
+
+        Ok(())
+    }
+
+    fn check_selection(&self, selection: Selection) -> Result<(), Error> {
+        self.check_bounds(selection.channel, selection.row)?;
+
+        let end_channel = selection.channel as u32 + selection.channels as u32;
+        let end_row = selection.row as u32 + selection.rows as u32;
+        if end_channel > self.channels as u32 || end_row > self.rows as u32 {
+            return Err(Error::OutOfBounds { channel: (end_channel - 1).min(u16::MAX as u32) as u16, row: (end_row - 1).min(u16::MAX as u32) as u16 });
+        }
+
+        Ok(())
+    }
+}
+
+/// A rectangle of notes in a pattern, starting at the top left.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Selection {
+    pub channel: u16,
+    pub row: u16,
+    pub channels: u16,
+    pub rows: u16
+}
+
+impl Selection {
+    pub fn new(channel: u16, row: u16, channels: u16, rows: u16) -> Self {
+        Self { channel, row, channels, rows }
+    }
 }
 
+#[derive(Clone)]
 pub struct Track {
     pub mod_type: ModuleType,
 
@@ -38,138 +235,208 @@ pub struct Track {
     pub pans: Vec<u8>,
     pub mix_volume: u8,
 
+    pub midi_macros: MidiMacros,
+
+    pub metadata: TrackMetadata,
+
     pub length_in_seconds: f64,
+    /// The order and row the track loops back to once it's finished, or None if it just restarts from the beginning.
+    pub loop_start: Option<(usize, usize)>,
     pub seek_table: Vec<SeekTable>
 }
 
+/// Information about a track that isn't needed to play it.
+#[derive(Debug, Clone, Default)]
+pub struct TrackMetadata {
+    pub title: String,
+    /// Not every format stores an artist, IT doesn't for one.
+    pub artist: Option<String>,
+    pub message: String,
+    /// The name and version of the tracker the track was made with.
+    pub tracker: String,
+    pub samples: Vec<SampleMetadata>,
+    /// The number of rows per beat and per measure, used by trackers to highlight rows.
+    pub highlight: (u8, u8)
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct SampleMetadata {
+    pub name: String,
+    pub filename: String
+}
+
 impl Track {
+    /// Get the number of channels used by the track, which is the width of its widest pattern.
+    pub fn num_channels(&self) -> u16 {
+        self.patterns.iter().map(|p| p.channels).max().unwrap_or(0)
+    }
+
+    /// Iterate over every note in the song as `(order, row, channel, note)`, going through the order list like the player
+    /// does: "+++" markers and missing patterns are skipped, and the song ends at the first "---" marker. Jumps and breaks
+    /// aren't followed, so every row of each pattern is included.
+    pub fn iter_song(&self) -> impl Iterator<Item = (usize, u16, u16, &Note)> {
+        let first = next_valid_order(&self.patterns, &self.orders, 0);
+        let orders = std::iter::successors(first, move |order| next_valid_order(&self.patterns, &self.orders, order + 1));
+
+        orders.flat_map(move |order| {
+            let pattern = &self.patterns[self.orders[order] as usize];
+            (0..pattern.rows).flat_map(move |row| {
+                (0..pattern.channels).map(move |channel| (order, row, channel, pattern.notes.get(channel as usize, row as usize)))
+            })
+        })
+    }
+
+    /// Recalculate the length, loop point and seek table. This needs to be done after changing the patterns, orders,
+    /// tempo or speed.
+    pub fn update_length(&mut self) {
+        let length = calculate_length(&self.patterns, &self.orders, self.tempo, self.speed);
+        self.length_in_seconds = length.seconds;
+        self.loop_start = length.loop_start;
+        self.seek_table = length.seek_table;
+    }
+
     /// Load the given Impulse Tracker file (.IT)
-    pub fn from_it(data: &[u8]) -> Result<Track, io::Error> {
-        let mut reader = mixr::binary_reader::BinaryReader::new(data);
-        if reader.read_string(4) != String::from("IMPM") {
-            return Err(io::Error::new(io::ErrorKind::InvalidData, "Expected \"IMPM\", not found."));
+    pub fn from_it(data: &[u8]) -> Result<Track, Error> {
+        let mut reader = BinaryReader::new(data);
+        if reader.read_string(4)? != "IMPM" {
+            return Err(Error::BadMagic { expected: "IMPM" });
         }
 
-        let title = reader.read_string(26);
-        super::log(format!("Loading \"{}\"...", title));
+        let title = reader.read_string(26)?;
+        log::info!("Loading \"{title}\"...");
 
-        reader.read_bytes(2); // pattern highlight
+        let highlight = (reader.read_u8()?, reader.read_u8()?);
         
-        let num_orders = reader.read_u16();
-        let num_instruments = reader.read_u16();
-        let num_samples = reader.read_u16();
-        let num_patterns = reader.read_u16();
+        let num_orders = reader.read_u16()?;
+        let num_instruments = reader.read_u16()?;
+        let num_samples = reader.read_u16()?;
+        let num_patterns = reader.read_u16()?;
 
-        reader.read_bytes(4); // created with tracker, not needed here.
+        let created_with = reader.read_u16()?;
+        reader.read_u16()?; // compatible with, not needed here.
 
-        let flags = reader.read_u16();
+        let flags = reader.read_u16()?;
         if (flags & 4) == 4 {
-            todo!("Instruments are not currently supported.");
+            return Err(Error::Unsupported("instruments"));
         }
 
-        reader.read_bytes(2); // special, not needed.
+        let special = reader.read_u16()?;
 
-        let global_volume = reader.read_u8();
-        let mix_volume = reader.read_u8();
-        let initial_speed = reader.read_u8();
-        let initial_tempo = reader.read_u8();
+        let global_volume = reader.read_u8()?;
+        let mix_volume = reader.read_u8()?;
+        let initial_speed = reader.read_u8()?;
+        let initial_tempo = reader.read_u8()?;
 
-        super::log(format!("gv: {global_volume}, mv: {mix_volume}, spd: {initial_speed}, tmp: {initial_tempo}"));
+        log::debug!("gv: {global_volume}, mv: {mix_volume}, spd: {initial_speed}, tmp: {initial_tempo}");
 
-        reader.read_bytes(12); // stuff we don't need.
+        reader.read_bytes(2)?; // separation and pitch wheel depth, not needed here.
 
-        let pans = reader.read_bytes(64).to_vec();
-        let vols = reader.read_bytes(64).to_vec();
+        let message_length = reader.read_u16()?;
+        let message_offset = reader.read_u32()?;
 
-        assert_eq!(reader.position, 0xC0);
+        reader.read_bytes(4)?; // reserved
 
-        let orders = reader.read_bytes(num_orders as usize).to_vec();
+        let pans = reader.read_bytes(64)?.to_vec();
+        let vols = reader.read_bytes(64)?.to_vec();
 
-        reader.position = (0xC0 + num_orders + num_instruments * 4) as usize;
+        let orders = reader.read_bytes(num_orders as usize)?.to_vec();
+
+        reader.seek(0xC0 + num_orders as usize + num_instruments as usize * 4)?;
         
         let mut samples = Vec::with_capacity(num_samples as usize);
+        let mut sample_metadata = Vec::with_capacity(num_samples as usize);
 
         for _ in 0..num_samples {
-            let offset = reader.read_u32();
+            let offset = reader.read_u32()?;
             let curr_pos = reader.position;
 
-            reader.position = offset as usize;
+            reader.seek(offset as usize)?;
 
-            if reader.read_string(4) != String::from("IMPS") {
-                return Err(io::Error::new(io::ErrorKind::InvalidData, "Expected \"IMPS\", not found."));
+            if reader.read_string(4)? != "IMPS" {
+                return Err(Error::BadMagic { expected: "IMPS" });
             }
 
-            let dos_name = reader.read_string(12);
-            reader.read_u8(); // seemingly unused byte.
+            let dos_name = reader.read_string(12)?;
+            reader.read_u8()?; // seemingly unused byte.
 
-            let s_global = reader.read_u8();
-            let s_flags = reader.read_u8();
+            let s_global = reader.read_u8()?;
+            let s_flags = reader.read_u8()?;
 
             let mut format = AudioFormat::default();
             format.format_type = if (s_flags & 2) == 2 { FormatType::I16 } else { FormatType::I8 };
             format.channels = if (s_flags & 4) == 4 { 2 } else { 1 };
             // todo, loops and stuff
 
-            let s_def_vol = reader.read_u8(); // default volume
+            let s_def_vol = reader.read_u8()?; // default volume
 
-            let s_name = reader.read_string(26);
-            super::log(format!("Loading {s_name} ({dos_name})..."));
+            let s_name = reader.read_string(26)?;
+            log::debug!("Loading {s_name} ({dos_name})...");
 
-            let s_cvt = reader.read_u8(); // convert, unused *yet* but will be later.
-            reader.read_u8(); // default pan, don't think it needs to be used.
+            let s_cvt = reader.read_u8()?; // convert, unused *yet* but will be later.
+            reader.read_u8()?; // default pan, don't think it needs to be used.
 
-            let s_length = reader.read_u32() * format.channels as u32 * format.bytes_per_sample() as u32;
-            let s_loop_start = reader.read_u32();
-            let s_loop_end = reader.read_u32();
-            format.sample_rate = reader.read_i32();
+            let s_frames = reader.read_u32()? as usize;
+            let s_length = s_frames * format.channels as usize * format.bytes_per_sample() as usize;
+            let s_loop_start = reader.read_u32()?;
+            let s_loop_end = reader.read_u32()?;
+            format.sample_rate = reader.read_i32()?;
+            if format.sample_rate < 4 {
+                // Nothing sensible can be done with rates this low, so fall back to the usual middle C rate.
+                format.sample_rate = 8363;
+            }
 
-            reader.read_bytes(8); // ignoring sustain stuff for now
+            reader.read_bytes(8)?; // ignoring sustain stuff for now
 
-            let pointer = reader.read_u32();
+            let pointer = reader.read_u32()?;
 
-            reader.position = pointer as usize;
-            let s_data = reader.read_bytes(s_length as usize);
+            reader.seek(pointer as usize)?;
+            let s_data = reader.read_bytes(s_length)?;
 
-            let s_loop = (s_flags & 16) == 16;
+            // Loops that run past the end of the sample are cut short, and empty loops are ignored.
+            let s_loop_end = s_loop_end.min(s_frames as u32);
+            let s_loop = (s_flags & 16) == 16 && s_loop_start < s_loop_end;
             samples.push(Sample::new(s_data, format, s_loop, s_loop_start as i32, if !s_loop { -1 } else { s_loop_end as i32 }, s_global, s_def_vol));
 
-            reader.position = curr_pos;
-        }
-
-        reader.position = (0xC0 + num_orders + num_instruments * 4 + num_samples * 4) as usize;
+            sample_metadata.push(SampleMetadata { name: s_name, filename: dos_name });
 
-        let mut p_cache = Vec::with_capacity(64);
-        for _ in 0..64 {
-            p_cache.push(PatternCache { mask: 0, note: 0, instrument: 0, volume: 0, effect: 0, eff_param: 0  });
+            reader.seek(curr_pos)?;
         }
 
-        let mut patterns = Vec::with_capacity(num_patterns as usize);
+        reader.seek(0xC0 + num_orders as usize + num_instruments as usize * 4 + num_samples as usize * 4)?;
+
+        // Patterns are decoded first and built afterwards, so that they are only as wide as the channels the track
+        // actually uses, rather than always being 64 channels wide.
+        let mut pattern_data = Vec::with_capacity(num_patterns as usize);
+        let mut num_channels = 1;
 
         for i in 0..num_patterns {
-            let offset = reader.read_u32();
+            let offset = reader.read_u32()?;
             if offset == 0 {
-                patterns.push(Pattern::new(64, 64));
+                pattern_data.push((64, Vec::new()));
                 continue;
             }
             let curr_pos = reader.position;
 
-            reader.position = offset as usize;
+            reader.seek(offset as usize)?;
+
+            reader.read_bytes(2)?; // length
+            let rows = reader.read_u16()?;
 
-            reader.read_bytes(2); // length
-            let rows = reader.read_u16();
+            reader.read_bytes(4)?; // empty data
 
-            reader.read_bytes(4); // empty data
+            // The previous values start again from scratch in every pattern.
+            let mut p_cache = PatternCache::new_table();
 
-            let mut pattern = Pattern::new(64, rows);
+            let mut notes = Vec::new();
 
             for r in 0..rows {
-                let mut c_var = reader.read_u8();
+                let mut c_var = reader.read_u8()?;
 
                 while c_var != 0 {
                     let channel = (c_var - 1) & 63;
                     let mut prev_var = &mut p_cache[channel as usize];
 
-                    let mask_variable = if (c_var & 128) == 128 { reader.read_u8() } else { prev_var.mask };
+                    let mask_variable = if (c_var & 128) == 128 { reader.read_u8()? } else { prev_var.mask };
                     prev_var.mask = mask_variable;
 
                     let mut note: u8 = 253;
@@ -179,25 +446,26 @@ impl Track {
                     let mut effect_param: u8 = 0;
 
                     if (mask_variable & 1) == 1 {
-                        note = reader.read_u8();
+                        note = reader.read_u8()?;
                         prev_var.note = note;
                     }
 
+                    // Instruments are stored starting at 1, with 0 meaning no instrument.
                     if (mask_variable & 2) == 2 {
-                        let inst = reader.read_u8() - 1;
-                        instrument = Some(inst);
+                        let inst = reader.read_u8()?;
+                        instrument = inst.checked_sub(1);
                         prev_var.instrument = inst;
                     }
 
                     if (mask_variable & 4) == 4 {
-                        let vol = reader.read_u8();
+                        let vol = reader.read_u8()?;
                         volume = Some(vol);
                         prev_var.volume = vol;
                     }
 
                     if (mask_variable & 8) == 8 {
-                        effect = reader.read_u8();
-                        effect_param = reader.read_u8();
+                        effect = reader.read_u8()?;
+                        effect_param = reader.read_u8()?;
 
                         prev_var.effect = effect;
                         prev_var.eff_param = effect_param;
@@ -208,7 +476,7 @@ impl Track {
                     }
 
                     if (mask_variable & 32) == 32 {
-                        instrument = Some(prev_var.instrument);
+                        instrument = prev_var.instrument.checked_sub(1);
                     }
 
                     if (mask_variable & 64) == 64 {
@@ -224,36 +492,70 @@ impl Track {
                     let mut octave = 0;
                     let effect = crate::utils::it_utils::get_effect(effect, effect_param);
 
+                    // IT note numbers are the same as MIDI ones, but only go up to B-9. Anything above that is a
+                    // note fade, other than the values used for note off/cut, and 253 which we use for no note.
                     match note {
                         255 => key = PianoKey::NoteOff,
                         254 => key = PianoKey::NoteCut,
                         253 => {},
-                        _ => {
-                            key = unsafe { std::mem::transmute(note % 12 + PianoKey::C as u8) };
-                            octave = note / 12;
-                        }
+                        0..=119 => if let Some(midi) = Note::from_midi(note, None, None, Effect::None) {
+                            key = midi.key;
+                            octave = midi.octave;
+                        },
+                        _ => key = PianoKey::NoteFade
                     }
 
                     let note = Note::new(key, octave, instrument, volume, effect);
-                    super::log(format!("Row: {r}, Channel: {channel}, Pattern: {i}, Note: {:?}", note));
-                    pattern.set_note(channel as u16, r, note);
+                    log::trace!("Row: {r}, Channel: {channel}, Pattern: {i}, Note: {:?}", note);
+                    notes.push((channel as u16, r, note));
+                    num_channels = num_channels.max(channel as u16 + 1);
 
-                    c_var = reader.read_u8();
+                    c_var = reader.read_u8()?;
                 }
             }
 
+            pattern_data.push((rows, notes));
+            reader.seek(curr_pos)?;
+        }
+
+        let mut patterns = Vec::with_capacity(pattern_data.len());
+        for (rows, notes) in pattern_data {
+            let mut pattern = Pattern::new(num_channels, rows);
+            for (channel, row, note) in notes {
+                pattern.set_note(channel, row, note);
+            }
+
             patterns.push(pattern);
-            reader.position = curr_pos;
         }
 
-        let (length, order_table) = calculate_length(&patterns, &orders, initial_tempo, initial_speed);
+        // The edit history sits between the pattern offsets and the MIDI configuration, so we need to skip past it.
+        if (special & 2) == 2 {
+            let num_history = reader.read_u16()?;
+            reader.read_bytes(num_history as usize * 8)?;
+        }
+
+        let midi_macros = if (flags & 128) == 128 || (special & 8) == 8 {
+            MidiMacros::from_bytes(reader.read_bytes(MIDI_CONFIG_LENGTH)?)
+        } else {
+            MidiMacros::it_default()
+        };
+
+        // Lines in the message are separated with carriage returns.
+        let message = if (special & 1) == 1 {
+            reader.seek(message_offset as usize)?;
+            reader.read_string(message_length as usize)?.replace('\r', "\n")
+        } else {
+            String::new()
+        };
+
+        let length = calculate_length(&patterns, &orders, initial_tempo, initial_speed);
 
         let mut total = 0;
-        for table in &order_table {
+        for table in &length.seek_table {
             total += 1 + table.rows.len();
         }
 
-        println!("DEBUG INFORMATION:\nTitle: {title}\nOrders: {num_orders}\nPatterns: {num_patterns}\nSamples: {num_samples}\nGV: {global_volume}, MV: {mix_volume}\nIT: {initial_tempo}, IS: {initial_speed}\nLength: {length:.2}s\nSeek table: {total} total entries");
+        log::debug!("Orders: {num_orders}, Patterns: {num_patterns}, Samples: {num_samples}, Length: {:.2}s, Seek table: {total} total entries", length.seconds);
 
         Ok(Track { 
             mod_type: ModuleType::IT,
@@ -269,12 +571,288 @@ impl Track {
             pans,
             mix_volume,
 
-            length_in_seconds: length,
-            seek_table: order_table
+            midi_macros,
+
+            metadata: TrackMetadata {
+                title,
+                artist: None,
+                message,
+                tracker: crate::utils::it_utils::get_tracker_name(created_with),
+                samples: sample_metadata,
+                highlight
+            },
+
+            length_in_seconds: length.seconds,
+            loop_start: length.loop_start,
+            seek_table: length.seek_table
         })
     }
 
-    
+    /// Save the track as an Impulse Tracker file (.IT)
+    /// Fails if a pattern is too big for IT to store, [`crate::convert::convert`] can be used to make it fit.
+    pub fn to_it(&self) -> Result<Vec<u8>, Error> {
+        // Rather than write something IT can't load, refuse to. `convert` can be used to make the track fit first.
+        for pattern in &self.patterns {
+            if pattern.rows > 200 {
+                return Err(Error::OutOfRange { name: "IT pattern length", value: pattern.rows as u32 });
+            }
+
+            if pattern.channels > 64 {
+                return Err(Error::OutOfRange { name: "number of IT channels", value: pattern.channels as u32 });
+            }
+        }
+
+        let mut writer = BinaryWriter::new();
+        let metadata = &self.metadata;
+
+        // IT expects the order list to always end with 255.
+        let mut orders = self.orders.clone();
+        if orders.last() != Some(&255) {
+            orders.push(255);
+        }
+
+        // IT uses carriage returns for new lines, and has a limit of 8000 characters.
+        let mut message = metadata.message.replace('\n', "\r").into_bytes();
+        message.truncate(8000);
+
+        writer.write_bytes(b"IMPM");
+        writer.write_bytes(&fixed_string(&metadata.title, 26));
+        writer.write_u8(metadata.highlight.0);
+        writer.write_u8(metadata.highlight.1);
+
+        writer.write_u16(orders.len() as u16);
+        writer.write_u16(0); // instruments
+        writer.write_u16(self.samples.len() as u16);
+        writer.write_u16(self.patterns.len() as u16);
+
+        // Created with and compatible with. We write the same things IT 2.14 does, so claim to be that.
+        writer.write_u16(0x0214);
+        writer.write_u16(0x0214);
+
+        // Stereo, linear slides, and an embedded MIDI configuration.
+        writer.write_u16(1 | 8 | 128);
+
+        // MIDI configuration, and a song message if there is one.
+        writer.write_u16(if message.is_empty() { 8 } else { 8 | 1 });
+
+        writer.write_u8(self.global_volume);
+        writer.write_u8(self.mix_volume);
+        writer.write_u8(self.speed);
+        writer.write_u8(self.tempo);
+
+        writer.write_u8(128); // separation
+        writer.write_u8(0); // pitch wheel depth
+
+        // The message length includes the null terminator. The offset is filled in once we know it.
+        writer.write_u16(if message.is_empty() { 0 } else { message.len() as u16 + 1 });
+        let message_offset_pos = writer.position();
+        writer.write_u32(0);
+
+        writer.write_u32(0); // reserved
+
+        for i in 0..64 {
+            writer.write_u8(self.pans.get(i).copied().unwrap_or(32));
+        }
+        writer.write_bytes(&[64; 64]); // channel volumes
+
+        writer.write_bytes(&orders);
+
+        // Offsets to each sample and pattern. These are all filled in later.
+        let offsets_pos = writer.position();
+        writer.write_bytes(&vec![0; (self.samples.len() + self.patterns.len()) * 4]);
+
+        writer.write_bytes(&self.midi_macros.to_bytes());
+
+        let mut offsets = Vec::with_capacity(self.samples.len() + self.patterns.len());
+
+        if !message.is_empty() {
+            let position = writer.position();
+            writer.set_position(message_offset_pos);
+            writer.write_u32(position as u32);
+            writer.set_position(position);
+
+            writer.write_bytes(&message);
+            writer.write_u8(0);
+        }
+
+        for (i, sample) in self.samples.iter().enumerate() {
+            offsets.push(writer.position() as u32);
+
+            let sample_metadata = metadata.samples.get(i).cloned().unwrap_or_default();
+            let (is_16bit, data) = it_sample_data(sample);
+            let stereo = sample.format.channels == 2;
+            let looping = sample.looping && sample.loop_start >= 0 && sample.loop_end > sample.loop_start;
+
+            writer.write_bytes(b"IMPS");
+            writer.write_bytes(&fixed_string(&sample_metadata.filename, 12));
+            writer.write_u8(0);
+
+            writer.write_u8(sample.global_volume);
+            writer.write_u8(1 | if is_16bit { 2 } else { 0 } | if stereo { 4 } else { 0 } | if looping { 16 } else { 0 });
+            writer.write_u8(sample.default_volume);
+
+            writer.write_bytes(&fixed_string(&sample_metadata.name, 26));
+
+            writer.write_u8(1); // convert, our samples are always signed.
+            writer.write_u8(32); // default pan, not used.
+
+            writer.write_u32(sample.length() as u32);
+            writer.write_u32(if looping { sample.loop_start as u32 } else { 0 });
+            writer.write_u32(if looping { sample.loop_end as u32 } else { 0 });
+            writer.write_u32(sample.format.sample_rate as u32);
+            writer.write_bytes(&[0; 8]); // sustain loop
+
+            // The data comes straight after the rest of the header.
+            writer.write_u32(writer.position() as u32 + 8);
+            writer.write_bytes(&[0; 4]); // vibrato
+
+            writer.write_bytes(&data);
+        }
+
+        for pattern in &self.patterns {
+            offsets.push(writer.position() as u32);
+
+            let data = pack_pattern(pattern);
+            if data.len() > u16::MAX as usize {
+                return Err(Error::OutOfRange { name: "IT pattern size", value: data.len() as u32 });
+            }
+
+            writer.write_u16(data.len() as u16);
+            writer.write_u16(pattern.rows);
+            writer.write_bytes(&[0; 4]);
+            writer.write_bytes(&data);
+        }
+
+        writer.set_position(offsets_pos);
+        for offset in offsets {
+            writer.write_u32(offset);
+        }
+
+        Ok(writer.get_data().to_vec())
+    }
+}
+
+/// Pad or cut the given string to exactly the given length in bytes.
+fn fixed_string(text: &str, length: usize) -> Vec<u8> {
+    let mut bytes = text.as_bytes().to_vec();
+    bytes.resize(length, 0);
+    bytes
+}
+
+/// Get the sample's data the way IT stores it, signed and with stereo samples stored one channel after the other.
+/// Returns whether the data is 16-bit, and the data itself.
+fn it_sample_data(sample: &Sample) -> (bool, Vec<u8>) {
+    let channels = sample.format.channels.max(1) as usize;
+
+    let (is_16bit, data) = match sample.format.format_type {
+        FormatType::I8 => (false, sample.data.clone()),
+        FormatType::I16 => (true, sample.data.clone()),
+        // Anything else gets converted to 16-bit.
+        _ => (true, (0..sample.length() * channels).flat_map(|i| ((sample.get_value(i) * i16::MAX as f64) as i16).to_le_bytes()).collect())
+    };
+
+    if channels == 1 {
+        return (is_16bit, data);
+    }
+
+    let size = if is_16bit { 2 } else { 1 };
+    let frames = data.len() / (size * channels);
+
+    let mut planar = Vec::with_capacity(data.len());
+    for c in 0..channels {
+        for frame in 0..frames {
+            let index = (frame * channels + c) * size;
+            planar.extend_from_slice(&data[index..index + size]);
+        }
+    }
+
+    (is_16bit, planar)
+}
+
+/// Get the IT note value for the given note, if it has one.
+fn it_note(note: &Note) -> Option<u8> {
+    match note.key {
+        PianoKey::None => None,
+        PianoKey::NoteOff => Some(255),
+        PianoKey::NoteCut => Some(254),
+        PianoKey::NoteFade => Some(246),
+        // IT only goes up to B-9.
+        _ => note.to_midi().filter(|note| *note <= 119)
+    }
+}
+
+/// Pack a pattern the same way IT does, which is the exact opposite of how `from_it` reads it. Values that are the
+/// same as the last ones in that channel are not written again.
+fn pack_pattern(pattern: &Pattern) -> Vec<u8> {
+    let mut data = Vec::new();
+    let mut p_cache = PatternCache::new_table();
+
+    for row in 0..pattern.rows {
+        // IT can only have 64 channels.
+        for channel in 0..pattern.channels.min(64) {
+            let note = pattern.notes.get(channel as usize, row as usize);
+            if !note.initialized {
+                continue;
+            }
+
+            let prev_var = &mut p_cache[channel as usize];
+            let mut mask = 0;
+
+            let key = it_note(note);
+            if let Some(key) = key {
+                if key == prev_var.note { mask |= 16 } else { mask |= 1 }
+            }
+
+            // Instruments are stored starting at 1.
+            let instrument = note.sample.and_then(|sample| sample.checked_add(1));
+            if let Some(instrument) = instrument {
+                if instrument == prev_var.instrument { mask |= 32 } else { mask |= 2 }
+            }
+
+            if let Some(volume) = note.volume {
+                if volume == prev_var.volume { mask |= 64 } else { mask |= 4 }
+            }
+
+            let (effect, effect_param) = crate::utils::it_utils::get_it_effect(note.effect);
+            if effect != 0 {
+                if effect == prev_var.effect && effect_param == prev_var.eff_param { mask |= 128 } else { mask |= 8 }
+            }
+
+            if mask == prev_var.mask {
+                data.push(channel as u8 + 1);
+            } else {
+                data.push((channel as u8 + 1) | 128);
+                data.push(mask);
+                prev_var.mask = mask;
+            }
+
+            if (mask & 1) == 1 {
+                prev_var.note = key.unwrap();
+                data.push(prev_var.note);
+            }
+
+            if (mask & 2) == 2 {
+                prev_var.instrument = instrument.unwrap();
+                data.push(prev_var.instrument);
+            }
+
+            if (mask & 4) == 4 {
+                prev_var.volume = note.volume.unwrap();
+                data.push(prev_var.volume);
+            }
+
+            if (mask & 8) == 8 {
+                prev_var.effect = effect;
+                prev_var.eff_param = effect_param;
+                data.push(effect);
+                data.push(effect_param);
+            }
+        }
+
+        data.push(0);
+    }
+
+    data
 }
 
 struct PatternCache {
@@ -286,29 +864,56 @@ struct PatternCache {
     pub eff_param: u8
 }
 
+impl PatternCache {
+    /// Create a cache for each of the 64 channels.
+    fn new_table() -> Vec<PatternCache> {
+        let mut p_cache = Vec::with_capacity(64);
+        for _ in 0..64 {
+            p_cache.push(PatternCache { mask: 0, note: 0, instrument: 0, volume: 0, effect: 0, eff_param: 0 });
+        }
+
+        p_cache
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct SeekValue {
+    pub row: usize,
     pub start: f64,
 
     pub speed: u8,
     pub tempo: u8
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct SeekTable {
+    pub order: usize,
     pub start: f64,
 
     pub rows: Vec<SeekValue>
 }
 
+/// The result of [`calculate_length`].
+pub(crate) struct TrackLength {
+    pub seconds: f64,
+    pub loop_start: Option<(usize, usize)>,
+    pub seek_table: Vec<SeekTable>
+}
+
+/// The most rows [`calculate_length`] will play through before giving up.
+const MAX_SIMULATED_ROWS: usize = 200_000;
+
 /// Precalculate the length of a track.
-fn calculate_length(patterns: &Vec<Pattern>, orders: &Vec<u8>, init_tempo: u8, init_speed: u8) -> (f64, Vec<SeekTable>) {
-    // Fairly simple, just plays through the entire track in order, picking up on any tempo/speed changes,
-    // and handling position jumps and pattern breaks.
+pub(crate) fn calculate_length(patterns: &[Pattern], orders: &[u8], init_tempo: u8, init_speed: u8) -> TrackLength {
+    // This simulates playing through the track row by row, picking up on any tempo/speed changes, and handling
+    // position jumps, pattern breaks, pattern loops and pattern delays.
+    // Every (order, row) that is played is remembered. The first time we land on one we've already played, the song
+    // has looped, and that position is where the loop starts. If the song runs off the end of the order list instead,
+    // then it doesn't loop and will just restart from the beginning.
 
     // The seek table contains two things:
-    // 1. The time in seconds at which each order starts
-    // 2. The time in seconds for each row in each order.
+    // 1. The time in seconds at which each order starts, in the order they are played.
+    // 2. The time in seconds for each row played in each order.
     // Why this route?
     // Well - The reason we store the time in seconds for each order is so that we can quickly look up
     // during seeking. Iterate through all orders, checking the starting time until we find one that
@@ -316,68 +921,168 @@ fn calculate_length(patterns: &Vec<Pattern>, orders: &Vec<u8>, init_tempo: u8, i
     // Then - loop through each row similarly to above. We now know the exact order and row we need,
     // and can seek to it.
     let mut seek_table = Vec::new();
-
-    let mut rows = Vec::new();
+    let mut visited = HashSet::new();
 
     let mut length = 0.0;
-    let mut last_length = 0.0;
 
-    let mut curr_tempo = init_tempo;
-    let mut curr_speed = init_speed;
+    let mut curr_tempo = init_tempo.max(1);
+    let mut curr_speed = init_speed.max(1);
 
-    let mut duration = (2.5 / curr_tempo as f64) * curr_speed as f64;
+    let channels = patterns.iter().map(|p| p.channels as usize).max().unwrap_or(0);
+    let mut loop_starts = vec![0; channels];
+    let mut loop_counts = vec![0u8; channels];
 
-    for order in 0..orders.len() - 1 {
-        let order = orders[order] as usize;
-        if order == 255 {
-            return (length, seek_table);
-        } else if order >= patterns.len() {
-            continue;
+    let mut order = match next_valid_order(patterns, orders, 0) {
+        Some(order) => order,
+        None => return TrackLength { seconds: 0.0, loop_start: None, seek_table }
+    };
+    let mut row = 0;
+
+    seek_table.push(SeekTable { order, start: length, rows: Vec::new() });
+
+    let mut rows_played = 0;
+
+    loop {
+        let pattern = &patterns[orders[order] as usize];
+
+        if !visited.insert((order, row)) {
+            return TrackLength { seconds: length, loop_start: Some((order, row)), seek_table };
         }
 
-        let pattern = &patterns[order];
-
-        'rowcount: for row in 0..pattern.rows {
-            for channel in 0..pattern.channels {
-                let note = pattern.notes.get(channel as usize, row as usize);
-
-                match note.effect {
-                    Effect::SetSpeed(speed) => {
-                        curr_speed = speed;
-                        duration = (2.5 / curr_tempo as f64) * curr_speed as f64;
-                    },
-                    Effect::Tempo(tempo) => {
-                        curr_tempo = tempo;
-                        duration = (2.5 / curr_tempo as f64) * curr_speed as f64;
-                    },
-
-                    Effect::PatternBreak(pos) => {
-                        length += duration;
-                        break 'rowcount
-                    },
-
-                    Effect::PositionJump(pos) => {
-                        if pos as usize <= order {
-                            return (length, seek_table);
-                        } else {
-                            length += duration;
-                            break 'rowcount;
-                        }
-                    }
+        // Nested pattern loops can make a song extremely long, so give up at some point rather than hanging.
+        rows_played += 1;
+        if rows_played > MAX_SIMULATED_ROWS {
+            log::warn!("Song is longer than {MAX_SIMULATED_ROWS} rows, stopping the length calculation there.");
+            return TrackLength { seconds: length, loop_start: None, seek_table };
+        }
 
-                    _ => {}
+        let mut break_row = None;
+        let mut jump_order = None;
+        let mut loop_row = None;
+        let mut row_delay = None;
+        let mut tick_delay = 0;
+        let mut tempo_slide = 0;
+
+        for channel in 0..pattern.channels as usize {
+            let note = pattern.notes.get(channel, row);
+
+            match note.effect {
+                Effect::SetSpeed(speed) if speed > 0 => curr_speed = speed,
+                Effect::Tempo(tempo) => {
+                    // T0x slides the tempo down, T1x slides it up, anything else sets it.
+                    match tempo & 0xF0 {
+                        0x00 => tempo_slide -= (tempo & 0xF) as i32,
+                        0x10 => tempo_slide += (tempo & 0xF) as i32,
+                        _ => curr_tempo = tempo
+                    }
+                },
+
+                Effect::PatternBreak(pos) => break_row = Some(pos as usize),
+                Effect::PositionJump(pos) => jump_order = Some(pos as usize),
+
+                Effect::Special(cmd) => {
+                    let value = cmd & 0xF;
+
+                    match cmd & 0xF0 {
+                        // SB0 sets the loop start, SBx loops back to it x times.
+                        0xB0 => {
+                            if value == 0 {
+                                loop_starts[channel] = row;
+                            } else if loop_counts[channel] == 0 {
+                                loop_counts[channel] = value;
+                                loop_row = Some(loop_starts[channel]);
+                            } else {
+                                loop_counts[channel] -= 1;
+                                if loop_counts[channel] > 0 {
+                                    loop_row = Some(loop_starts[channel]);
+                                } else {
+                                    loop_starts[channel] = row + 1;
+                                }
+                            }
+                        },
+
+                        // SEx repeats the row x times, only the first one in a row counts.
+                        0xE0 if row_delay.is_none() => row_delay = Some(value as u32),
+
+                        // S6x adds x ticks to the row.
+                        0x60 => tick_delay += value as u32,
+
+                        _ => {}
+                    }
                 }
+
+                _ => {}
+            }
+        }
+
+        if let Some(table) = seek_table.last_mut() {
+            table.rows.push(SeekValue { row, start: length, speed: curr_speed, tempo: curr_tempo });
+        }
+
+        let ticks = curr_speed as u32 * (1 + row_delay.unwrap_or(0)) + tick_delay;
+        for tick in 0..ticks {
+            // Tempo slides happen on every tick except the first.
+            if tick % curr_speed as u32 != 0 {
+                curr_tempo = (curr_tempo as i32 + tempo_slide).clamp(32, 255) as u8;
+            }
+
+            length += 2.5 / curr_tempo as f64;
+        }
+
+        if let Some(loop_row) = loop_row {
+            // The rows inside the loop are about to be played again, so they shouldn't count as the song looping.
+            for r in loop_row..=row {
+                visited.remove(&(order, r));
+            }
+
+            row = loop_row;
+            continue;
+        }
+
+        let next_order = if break_row.is_some() || jump_order.is_some() {
+            row = break_row.unwrap_or(0);
+            jump_order.unwrap_or(order + 1)
+        } else {
+            row += 1;
+            if row < pattern.rows as usize {
+                continue;
             }
 
-            rows.push(SeekValue { start: length, speed: curr_speed, tempo: curr_tempo });
+            row = 0;
+            order + 1
+        };
+
+        let prev_order = order;
+        order = match next_valid_order(patterns, orders, next_order) {
+            Some(order) => order,
+            None => return TrackLength { seconds: length, loop_start: None, seek_table }
+        };
 
-            length += duration;
+        if row >= patterns[orders[order] as usize].rows as usize {
+            row = 0;
         }
 
-        seek_table.push(SeekTable { start: last_length, rows: rows.clone() });
-        rows.clear();
-        last_length = length;
+        // Pattern loops don't carry over between patterns. A jump back into the same order keeps them, otherwise a
+        // jump over an SBx would loop forever, as the loop's rows are never remembered as visited.
+        if order != prev_order {
+            loop_starts.iter_mut().for_each(|start| *start = 0);
+            loop_counts.iter_mut().for_each(|count| *count = 0);
+        }
+
+        seek_table.push(SeekTable { order, start: length, rows: Vec::new() });
     }
+}
 
-    (length, seek_table)
-}
\ No newline at end of file
+/// Find the first playable order starting at the given one, skipping "+++" markers and missing patterns.
+/// Returns None if the end of the song is reached first.
+pub(crate) fn next_valid_order(patterns: &[Pattern], orders: &[u8], mut order: usize) -> Option<usize> {
+    while order < orders.len() {
+        match orders[order] {
+            255 => return None,
+            pattern if (pattern as usize) < patterns.len() && patterns[pattern as usize].rows > 0 => return Some(order),
+            _ => order += 1
+        }
+    }
+
+    None
+}
@@ -1,8 +1,29 @@
-use mixr::{ChannelProperties, BufferDescription, DataType, AudioFormat};
+use std::collections::{HashMap, HashSet};
+use std::ops::Deref;
+use std::sync::Arc;
+use std::sync::mpsc::{self, Receiver, Sender};
 
-use crate::{track::Track, PianoKey, Effect, sample::Sample, Note};
+use mixr::{ChannelProperties, BufferDescription, DataType, AudioFormat, FormatType};
 
-pub const SAMPLE_RATE: i32 = 48000;
+use crate::{track::Track, PianoKey, Effect, sample::Sample, Note, midi::{self, MacroCommand}, mixer::Mixer, event::{EventQueue, PlayerEvent, TimedEvent}, handle::{PlayerHandle, PlayerCommand}};
+
+/// The sample rate used if you don't need a specific one.
+pub const DEFAULT_SAMPLE_RATE: i32 = 48000;
+
+/// The number of extra voices a player gets by default for playing notes live.
+pub const LIVE_VOICES: u16 = 8;
+
+/// The most filtered copies of samples a player will keep.
+const MAX_FILTER_BUFFERS: usize = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NoteTarget {
+    /// Play the note on one of the track's channels, replacing whatever is playing on it.
+    Channel(u16),
+
+    /// Play the note on a voice the track doesn't use.
+    FreeVoice
+}
 
 struct TrackChannel {
     properties: ChannelProperties,
@@ -15,27 +36,170 @@ struct TrackChannel {
     pitch_memory: u8,
 
     offset_memory: u8,
-    high_offset: usize
+    high_offset: usize,
+
+    active_macro: u8,
+    filter_cutoff: u8,
+    filter_resonance: u8,
+
+    /// The row SB0 last set as the start of a pattern loop, and how many more times SBx will loop back to it.
+    pattern_loop_start: usize,
+    pattern_loop_count: u8,
+
+    playing: bool,
+    position: f64,
+
+    muted: bool,
+    solo: bool,
+
+    /// Live voices are the ones past the end of the track's channels, used for notes played with `play_note`.
+    live: bool
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ChannelState {
+    pub muted: bool,
+    pub solo: bool,
+
+    /// Whether the channel can currently be heard, taking into account the mute and solo state of every channel.
+    pub audible: bool,
+
+    pub playing: bool,
+    /// The sample last played on the channel.
+    pub sample: Option<u8>,
+    /// The note's volume, from 0 to 64, after any volume effects.
+    pub volume: u8,
+    /// The position in the sample, in frames.
+    pub position: f64
+}
+
+impl TrackChannel {
+    fn new(pan: u8, interpolation: mixr::InterpolationType, live: bool) -> Self {
+        let mut properties = ChannelProperties::default();
+        properties.interpolation = interpolation;
+        properties.panning = pan as f64 / 64.0;
+
+        // A pan value of >= 128 means the channel is disabled and will not be played.
+        Self {
+            properties,
+            enabled: pan < 128,
+            current_sample: None,
+            note_volume: 0,
+
+            vol_memory: 0,
+            pitch_memory: 0,
+
+            offset_memory: 0,
+            high_offset: 0,
+
+            active_macro: 0,
+            filter_cutoff: 127,
+            filter_resonance: 0,
+
+            pattern_loop_start: 0,
+            pattern_loop_count: 0,
+
+            playing: false,
+            position: 0.0,
+
+            muted: false,
+            solo: false,
+
+            live
+        }
+    }
+
+    fn audible(&self, solo_active: bool) -> bool {
+        !self.muted && (self.solo || self.live || !solo_active)
+    }
+
+    /// Get the properties to send to the mixer. Mute and solo are only applied here, so that effects are still
+    /// processed as normal on muted channels.
+    fn mix_properties(&self, solo_active: bool) -> ChannelProperties {
+        let mut properties = self.properties;
+        if !self.audible(solo_active) {
+            properties.volume = 0.0;
+        }
+
+        properties
+    }
+}
+
+/// The track a [`TrackPlayer`] plays. Use `Shared` to get a `TrackPlayer<'static>` that can be moved onto
+/// another thread, such as inside an audio callback.
+#[derive(Clone)]
+pub enum TrackSource<'a> {
+    Borrowed(&'a Track),
+    Shared(Arc<Track>)
+}
+
+impl<'a> Deref for TrackSource<'a> {
+    type Target = Track;
+
+    fn deref(&self) -> &Track {
+        match self {
+            TrackSource::Borrowed(track) => track,
+            TrackSource::Shared(track) => track
+        }
+    }
+}
+
+impl<'a> From<&'a Track> for TrackSource<'a> {
+    fn from(track: &'a Track) -> Self {
+        TrackSource::Borrowed(track)
+    }
+}
+
+impl From<Arc<Track>> for TrackSource<'static> {
+    fn from(track: Arc<Track>) -> Self {
+        TrackSource::Shared(track)
+    }
+}
+
+impl From<Track> for TrackSource<'static> {
+    fn from(track: Track) -> Self {
+        TrackSource::Shared(Arc::new(track))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PlaybackState {
+    /// The song is playing and has not looped yet.
+    Playing,
+
+    /// The song has looped the given number of times, and is still playing.
+    Looped(u32),
+
+    /// The song has reached its end, or the maximum number of loops, and no more audio will be produced.
+    Finished
 }
 
 pub struct TrackPlayer<'a> {
-    track: &'a Track,
-    system: mixr::system::AudioSystem,
-    buffers: Vec<i32>,
+    track: TrackSource<'a>,
+    mixer: Mixer,
+    buffers: Vec<usize>,
+    filter_buffers: HashMap<(u8, u8, u8), usize>,
+
+    sample_rate: i32,
 
     current_half_sample: u32,
+    elapsed_half_samples: u64,
     half_samples_per_tick: u32,
-    current_tick: u8,
+    current_tick: u16,
     current_speed: u8,
     current_tempo: u8,
 
     current_order: usize,
     current_row: usize,
 
-    next_row: usize,
-    next_order: usize,
-
-    should_jump: bool,
+    // Effects that change how long the current row lasts, and where to go once it's over. These are all set on its
+    // first tick.
+    break_row: Option<usize>,
+    jump_order: Option<usize>,
+    loop_row: Option<usize>,
+    row_delay: Option<u8>,
+    tick_delay: u8,
+    tempo_slide: i32,
 
     channels: Vec<TrackChannel>,
 
@@ -44,52 +208,68 @@ pub struct TrackPlayer<'a> {
 
     global_volume: u8,
 
-    pub looping: bool
+    solo_active: bool,
+
+    events: EventQueue,
+    event_order: Option<usize>,
+
+    commands: Receiver<PlayerCommand>,
+    command_sender: Sender<PlayerCommand>,
+
+    paused: bool,
+
+    next_live_voice: usize,
+
+    state: PlaybackState,
+    loop_count: u32,
+    max_loops: Option<u32>,
+    looped: bool,
+
+    /// Every (order, row) played since the song last looped. Landing on one of these again means it's repeating
+    /// itself, the same way `calculate_length` finds where a song loops.
+    visited: HashSet<(usize, usize)>
 }
 
 impl<'a> TrackPlayer<'a> {
-    pub fn new(track: &'a Track) -> Self {
-        let mut system = mixr::system::AudioSystem::new(SAMPLE_RATE,64);
-        
-        let mut buffers = Vec::with_capacity(track.samples.len());
-        for i in 0..track.samples.len() {
-            let sample = &track.samples[i];
-            let buffer = system.create_buffer(BufferDescription { data_type: DataType::Pcm, format: sample.format }, Some(&sample.data));
-            buffers.push(buffer);
-        }
-
-        let mut channels = Vec::with_capacity(system.num_channels() as usize);
-        for i in 0..system.num_channels() {
-            let mut properties = ChannelProperties::default();
-            properties.interpolation = mixr::InterpolationType::Linear;
-
-            let pan = track.pans[i as usize];
-            properties.panning = pan as f64 / 64.0;
-            // A pan value of >= 128 means the channel is disabled and will not be played.
-            channels.push(TrackChannel {
-                properties,
-                enabled: pan < 128,
-                current_sample: None,
-                note_volume: 0,
-
-                vol_memory: 0,
-                pitch_memory: 0,
-
-                offset_memory: 0,
-                high_offset: 0
-            });
-        }
-
-        let half_samples_per_tick = calculate_half_samples_per_tick(track.tempo);
+    /// Create a player with one voice per channel in the track, plus [`LIVE_VOICES`] for playing notes live.
+    /// The track can either be borrowed, or shared/owned with an [`Arc`] for a player that isn't tied to a lifetime.
+    pub fn new(track: impl Into<TrackSource<'a>>, sample_rate: i32) -> Self {
+        let track = track.into();
+        let voices = track.num_channels() + LIVE_VOICES;
+        Self::with_voices(track, sample_rate, voices)
+    }
+
+    /// Create a player with the given number of voices. Any pattern channels beyond this number will not be played, and
+    /// any voices beyond the track's channels are used for playing notes live.
+    pub fn with_voices(track: impl Into<TrackSource<'a>>, sample_rate: i32, voices: u16) -> Self {
+        let track = track.into();
+        let mut mixer = Mixer::new(sample_rate, voices, 0);
+        let buffers = create_buffers(&mut mixer, &track);
+        let filter_buffers = create_filter_buffers(&mut mixer, &track);
+
+        let mut channels = Vec::with_capacity(voices as usize);
+        let num_channels = track.num_channels();
+        for i in 0..voices {
+            channels.push(TrackChannel::new(channel_pan(&track, i), mixr::InterpolationType::Linear, i >= num_channels));
+        }
+
+        let half_samples_per_tick = calculate_half_samples_per_tick(track.tempo, sample_rate);
         let speed = track.speed;
         let tempo = track.tempo;
+        let global_volume = track.global_volume;
+
+        let (command_sender, commands) = mpsc::channel();
 
-        Self { 
+        let mut player = Self { 
             track, 
-            system,
+            mixer,
             buffers,
+            filter_buffers,
+
+            sample_rate,
 
             current_half_sample: 0,
+            elapsed_half_samples: 0,
             half_samples_per_tick,
             current_tick: 0,
             current_speed: speed,
@@ -98,257 +278,736 @@ impl<'a> TrackPlayer<'a> {
             current_order: 0,
             current_row: 0,
             
-            next_row: 0,
-            next_order: 0,
-
-            should_jump: false,
+            break_row: None,
+            jump_order: None,
+            loop_row: None,
+            row_delay: None,
+            tick_delay: 0,
+            tempo_slide: 0,
 
             channels,
 
-            looping: true,
             pitch_tuning: 1.0,
             tempo_tuning: 1.0,
 
-            global_volume: track.global_volume
-        }
+            global_volume,
+
+            solo_active: false,
+
+            events: EventQueue::default(),
+            event_order: None,
+
+            commands,
+            command_sender,
+
+            paused: false,
+
+            next_live_voice: 0,
+
+            state: PlaybackState::Playing,
+            loop_count: 0,
+            max_loops: None,
+            looped: false,
+
+            visited: HashSet::new()
+        };
+
+        // Make sure we start on an order that can actually be played.
+        player.reset();
+        player
     }
 
+    /// Advance the player by a single half sample (one channel of a stereo frame), returning its value.
     pub fn advance(&mut self) -> f64 {
-        let pattern = &self.track.patterns[self.track.orders[self.current_order] as usize];
+        // Commands normally wait for a tick boundary, but a paused player might not be on one.
+        if self.current_half_sample == 0 || self.paused {
+            self.process_commands();
+        }
+
+        if self.state == PlaybackState::Finished || self.paused {
+            return 0.0;
+        }
 
         if self.current_half_sample == 0 {
-            for c in 0..pattern.channels {
-                let mut channel = &mut self.channels[c as usize];
+            self.process_tick();
+        }
 
-                if !channel.enabled {
-                    continue;
+        self.current_half_sample += 1;
+        self.elapsed_half_samples += 1;
+
+        if self.current_half_sample >= self.half_samples_per_tick {
+            self.current_half_sample = 0;
+            self.next_tick();
+
+            if self.state == PlaybackState::Finished {
+                return 0.0;
+            }
+        }
+
+        self.mixer.advance()
+    }
+
+    /// Render interleaved stereo audio into the given buffer, returning the number of frames written.
+    /// If this is less than the buffer can hold, the song has ended.
+    pub fn render(&mut self, out: &mut [f32]) -> usize {
+        self.render_with(out, None, |value| value as f32)
+    }
+
+    /// Render interleaved stereo audio as 16-bit integers. See [`TrackPlayer::render`].
+    pub fn render_i16(&mut self, out: &mut [i16]) -> usize {
+        self.render_with(out, None, |value| (value.clamp(-1.0, 1.0) * i16::MAX as f64) as i16)
+    }
+
+    /// Render interleaved stereo audio as 64-bit floats. See [`TrackPlayer::render`].
+    pub fn render_f64(&mut self, out: &mut [f64]) -> usize {
+        self.render_with(out, None, |value| value)
+    }
+
+    /// Render the master mix, as well as each channel into its own stem. Stems must be enabled first with
+    /// [`TrackPlayer::set_stems_enabled`], and there must be a buffer for each stem that is the same length as `out`.
+    pub fn render_stems(&mut self, out: &mut [f32], stems: &mut [Vec<f32>]) -> usize {
+        self.render_with(out, Some(stems), |value| value as f32)
+    }
+
+    fn render_with<T>(&mut self, out: &mut [T], mut stems: Option<&mut [Vec<T>]>, convert: impl Fn(f64) -> T) -> usize {
+        // Only ever render whole frames.
+        let length = out.len() - out.len() % 2;
+        let mut position = 0;
+
+        while position < length {
+            if self.current_half_sample == 0 || self.paused {
+                self.process_commands();
+            }
+
+            if self.state == PlaybackState::Finished {
+                break;
+            }
+
+            // Nothing moves while paused, so just fill the rest with silence. Playback picks up mid-tick if that's
+            // where it was paused.
+            if self.paused {
+                for i in position..length {
+                    out[i] = convert(0.0);
+
+                    if let Some(stems) = stems.as_deref_mut() {
+                        for stem in stems.iter_mut() {
+                            stem[i] = convert(0.0);
+                        }
+                    }
                 }
 
-                let note = pattern.notes.get(c as usize, self.current_row);
-                
-                if !note.initialized {
+                position = length;
+                break;
+            }
+
+            if self.current_half_sample == 0 {
+                self.process_tick();
+            }
+
+            // Mix everything up until the end of this tick (or the buffer) in one go, as nothing can change until then.
+            // The tick can get shorter while paused mid-way through it, if the tempo tuning changes.
+            let count = (self.half_samples_per_tick.saturating_sub(self.current_half_sample) as usize).min(length - position);
+            for i in position..position + count {
+                out[i] = convert(self.mixer.advance());
+
+                if let Some(stems) = stems.as_deref_mut() {
+                    for (s, stem) in stems.iter_mut().enumerate().take(self.mixer.num_stems()) {
+                        stem[i] = convert(self.mixer.stem_value(s));
+                    }
+                }
+            }
+
+            position += count;
+            self.current_half_sample += count as u32;
+            self.elapsed_half_samples += count as u64;
+
+            if self.current_half_sample >= self.half_samples_per_tick {
+                self.current_half_sample = 0;
+                self.next_tick();
+            }
+        }
+
+        position / 2
+    }
+
+    /// Play a note on top of the track. `FreeVoice` uses one of the voices the track doesn't use, so the track is
+    /// left undisturbed. Returns the voice the note is playing on, or None if the sample doesn't exist, the key isn't
+    /// one that can be played (such as a note off), or there is no voice to play it on.
+    pub fn play_note(&mut self, target: NoteTarget, sample: u8, key: PianoKey, octave: u8, volume: u8) -> Option<u16> {
+        if sample as usize >= self.buffers.len() || key.semitone().is_none() {
+            return None;
+        }
+
+        let voice = match target {
+            NoteTarget::Channel(channel) if (channel as usize) < self.channels.len() => channel,
+            NoteTarget::Channel(_) => return None,
+            NoteTarget::FreeVoice => self.free_voice()?
+        };
+
+        self.trigger_note(voice, sample, key, octave, volume.min(64));
+
+        Some(voice)
+    }
+
+    /// Stop a note on the given voice, such as one started by [`TrackPlayer::play_note`].
+    pub fn stop_note(&mut self, voice: u16) {
+        if let Some(channel) = self.channels.get_mut(voice as usize) {
+            channel.current_sample = None;
+            channel.playing = false;
+            self.mixer.stop(voice);
+        }
+    }
+
+    /// Get the number of voices that can be used for live notes without disturbing the track.
+    pub fn num_free_voices(&self) -> u16 {
+        self.channels.iter().filter(|channel| channel.live).count() as u16
+    }
+
+    /// Find a voice for a live note, preferring one that isn't playing anything. If they are all busy, the voices
+    /// are taken in turn.
+    fn free_voice(&mut self) -> Option<u16> {
+        let first = self.channels.iter().position(|channel| channel.live)?;
+        if let Some(voice) = self.channels[first..].iter().position(|channel| !channel.playing) {
+            return Some((first + voice) as u16);
+        }
+
+        let voice = first + self.next_live_voice % (self.channels.len() - first);
+        self.next_live_voice += 1;
+
+        Some(voice as u16)
+    }
+
+    /// Start playing a sample on the given voice.
+    fn trigger_note(&mut self, voice: u16, sample_id: u8, key: PianoKey, octave: u8, volume: u8) {
+        let sample = &self.track.samples[sample_id as usize];
+        let channel = &mut self.channels[voice as usize];
+
+        let properties = &mut channel.properties;
+        properties.volume = calculate_volume(volume, sample, self.global_volume, self.track.mix_volume);
+        properties.speed = calculate_speed(key, octave, sample.multiplier) * self.pitch_tuning;
+        properties.looping = sample.looping;
+        properties.loop_start = sample.loop_start;
+        properties.loop_end = sample.loop_end;
+
+        let buffer = get_buffer(&self.buffers, &self.filter_buffers, sample_id, channel);
+        self.mixer.play_buffer(buffer, voice, channel.mix_properties(self.solo_active));
+
+        channel.current_sample = Some(sample_id);
+        channel.note_volume = volume;
+        channel.playing = true;
+        channel.position = 0.0;
+
+        self.events.push(self.elapsed_half_samples / 2, PlayerEvent::NoteTriggered { channel: voice, sample: sample_id, key, octave, volume });
+    }
+
+    /// Apply any commands sent from a [`PlayerHandle`].
+    fn process_commands(&mut self) {
+        while let Ok(command) = self.commands.try_recv() {
+            match command {
+                PlayerCommand::SeekSeconds(seconds) => { self.seek_seconds(seconds); },
+                PlayerCommand::SeekPosition(order, row) => { self.seek_position(order, row); },
+
+                PlayerCommand::SetPaused(paused) => self.set_paused(paused),
+                PlayerCommand::Stop => self.stop(),
+
+                PlayerCommand::SetPitchTuning(tuning) => self.set_pitch_tuning(tuning),
+                PlayerCommand::SetTempoTuning(tuning) => self.set_tempo_tuning(tuning),
+
+                PlayerCommand::SetChannelMuted(channel, muted) => self.set_channel_muted(channel, muted),
+                PlayerCommand::SetChannelSolo(channel, solo) => self.set_channel_solo(channel, solo)
+            }
+        }
+    }
+
+    /// Process all notes and effects for the current tick.
+    fn process_tick(&mut self) {
+        let pattern_index = self.track.orders[self.current_order];
+        let num_channels = self.track.patterns[pattern_index as usize].channels;
+        let frame = self.elapsed_half_samples / 2;
+
+        if self.current_tick == 0 {
+            if self.event_order != Some(self.current_order) {
+                self.event_order = Some(self.current_order);
+                self.events.push(frame, PlayerEvent::OrderChanged { order: self.current_order, pattern: pattern_index });
+            }
+
+            self.events.push(frame, PlayerEvent::RowChanged { order: self.current_order, row: self.current_row });
+            self.clear_row_effects();
+        }
+
+        for c in 0..num_channels.min(self.channels.len() as u16) {
+            let mut channel = &mut self.channels[c as usize];
+
+            if !channel.enabled {
+                continue;
+            }
+
+            let note = *self.track.patterns[pattern_index as usize].notes.get(c as usize, self.current_row);
+            
+            if !note.initialized {
+                continue;
+            }
+
+            if self.current_tick == 0 {
+                if note.key == PianoKey::NoteCut || note.key == PianoKey::NoteOff || note.key == PianoKey::NoteFade {
+                    channel.current_sample = None;
+                    channel.note_volume = 0;
+                    channel.playing = false;
+                    self.mixer.stop(c);
                     continue;
                 }
 
-                if self.current_tick == 0 {
-                    if note.key == PianoKey::NoteCut || note.key == PianoKey::NoteOff || note.key == PianoKey::NoteFade {
-                        channel.current_sample = None;
-                        channel.note_volume = 0;
-                        self.system.stop(c).unwrap();
-                        continue;
+                let mut sample_id = note.sample;
+                if sample_id.is_none() {
+                    sample_id = channel.current_sample;
+                }
+
+                if let Some(sample_id) = sample_id {
+                    if note.key != PianoKey::None && sample_id < self.buffers.len() as u8 {
+                        let volume = note.volume.unwrap_or(self.track.samples[sample_id as usize].default_volume);
+                        self.trigger_note(c, sample_id, note.key, note.octave, volume);
+                        channel = &mut self.channels[c as usize];
                     }
+                }
+
+                if let (Some(volume), Some(sample)) = (note.volume, channel.current_sample) {
+                    let sample = &self.track.samples[sample as usize];
+                    channel.properties.volume = calculate_volume(volume, sample, self.global_volume, self.track.mix_volume);
+                    self.mixer.set_channel_properties(c, channel.mix_properties(self.solo_active));
+                    channel.note_volume = volume;
+                }
+            }
 
-                    let mut sample_id = note.sample;
-                    if sample_id.is_none() {
-                        sample_id = channel.current_sample;
+            if self.current_tick == 0 && note.effect != Effect::None {
+                self.events.push(frame, PlayerEvent::EffectExecuted { channel: c, effect: note.effect });
+            }
+
+            match note.effect {
+                Effect::None => {},
+                Effect::SetSpeed(speed) => if self.current_tick == 0 && speed > 0 { self.current_speed = speed },
+                // A break and a jump on the same row go to the break's row in the jump's order.
+                Effect::PositionJump(pos) => if self.current_tick == 0 { self.jump_order = Some(pos as usize) },
+                Effect::PatternBreak(pos) => if self.current_tick == 0 { self.break_row = Some(pos as usize) },
+                Effect::VolumeSlide(value) => {
+                    // If the note parameter is 0, we just fetch the last one stored in memory.
+                    // If the last parameter is also 0 then nothing happens.
+                    let mut vol_param = if value == 0 { channel.vol_memory } else { value };
+                    channel.vol_memory = vol_param;
+
+                    // Handle DFy and DxF, if 'F' is set then the volume slide only occurs on the first tick.
+                    // However, if value is D0F, then ignore, as this is not a fine volume slide.
+                    // Volume slide occurs on every tick except the first, **unless** it is D0F.
+                    if channel.current_sample.is_none() || (self.current_tick == 0 && ((vol_param & 0xF0) != 0xF0 && (vol_param & 0xF) != 0xF)) ||
+                        (((vol_param & 0xF0) == 0xF0 || ((vol_param & 0xF) == 0xF && (vol_param & 0xF0) != 0)) && self.current_tick != 0) {
+                        continue;
                     }
 
-                    if let Some(sample_id) = sample_id {
-                        if note.key != PianoKey::None && sample_id < self.buffers.len() as u8 {
-                            let sample = &self.track.samples[sample_id as usize];
-                            let properties = &mut channel.properties;
-                            let volume = note.volume.unwrap_or(sample.default_volume);
-                            properties.volume = ((volume as u32 * sample.global_volume as u32 * 64 * self.global_volume as u32) >> 18) as f64 / 128.0 * (self.track.mix_volume as f64 / u8::MAX as f64);
-                            properties.speed = calculate_speed(note.key, note.octave, sample.multiplier) * self.pitch_tuning;
-                            properties.looping = sample.looping;
-                            properties.loop_start = sample.loop_start;
-                            properties.loop_end = sample.loop_end;
-
-                            self.system.play_buffer(self.buffers[sample_id as usize], c, channel.properties).unwrap();
-                            
-                            channel.current_sample = note.sample;
-                            channel.note_volume = volume;
-                        }
+                    let sample_id = channel.current_sample.unwrap();
+
+                    let mut volume = channel.note_volume as i32;
+
+                    // If the volume parameter is DFx then we need to remove the F so that the volume slide
+                    // works as usual, otherwise it would think it's a value of 240 + x
+                    if (vol_param & 0xF0) == 0xF0 {
+                        vol_param = vol_param & 0x0F
                     }
 
-                    if let (Some(volume), Some(sample)) = (note.volume, channel.current_sample) {
-                        let sample = &self.track.samples[sample as usize];
-                        channel.properties.volume = ((volume as u32 * sample.global_volume as u32 * 64 * self.global_volume as u32) >> 18) as f64 / 128.0 * (self.track.mix_volume as f64 / u8::MAX as f64);
-                        self.system.set_channel_properties(c, channel.properties).unwrap();
-                        channel.note_volume = volume;
+                    // D0y decreases volume by y units.
+                    // Dx0 increases volume by x units.
+                    if vol_param < 16 {
+                        volume -= vol_param as i32;
+                    } else {
+                        volume += vol_param as i32 / 16;
                     }
-                }
 
-                match note.effect {
-                    Effect::None => {},
-                    Effect::SetSpeed(speed) => if self.current_tick == 0 { self.current_speed = speed },
-                    Effect::PositionJump(pos) => {
-                        self.next_row = 0;
-                        self.next_order = pos as usize;
-                        self.should_jump = true;
-                    },
-                    Effect::PatternBreak(pos) => {
-                        self.next_order = self.current_order + 1;
-                        self.next_row = pos as usize;
-                        self.should_jump = true;
-                    },
-                    Effect::VolumeSlide(value) => {
-                        // If the note parameter is 0, we just fetch the last one stored in memory.
-                        // If the last parameter is also 0 then nothing happens.
-                        let mut vol_param = if value == 0 { channel.vol_memory } else { value };
-                        channel.vol_memory = vol_param;
-
-                        // Handle DFy and DxF, if 'F' is set then the volume slide only occurs on the first tick.
-                        // However, if value is D0F, then ignore, as this is not a fine volume slide.
-                        // Volume slide occurs on every tick except the first, **unless** it is D0F.
-                        if channel.current_sample.is_none() || (self.current_tick == 0 && ((vol_param & 0xF0) != 0xF0 && (vol_param & 0xF) != 0xF)) ||
-                            (((vol_param & 0xF0) == 0xF0 || ((vol_param & 0xF) == 0xF && (vol_param & 0xF0) != 0)) && self.current_tick != 0) {
-                            continue;
-                        }
+                    // Volume cannot exceed 64.
+                    channel.note_volume = volume.clamp(0, 64) as u8;
 
-                        let sample_id = channel.current_sample.unwrap();
+                    let sample = &self.track.samples[sample_id as usize];
+                    channel.properties.volume = calculate_volume(channel.note_volume, sample, self.global_volume, self.track.mix_volume);
+                    self.mixer.set_channel_properties(c, channel.mix_properties(self.solo_active));
+                },
+                Effect::PortamentoDown(value) => {
+                    let mut pitch_param = if value == 0 { channel.pitch_memory } else { value };
+                    channel.pitch_memory = pitch_param;
 
-                        let mut volume = channel.note_volume as i32;
+                    if ((pitch_param & 0xF0) >= 0xE0 && self.current_tick != 0) || self.current_tick == 0 && (pitch_param & 0xF0) < 0xE0 {
+                        continue;
+                    }
 
-                        // If the volume parameter is DFx then we need to remove the F so that the volume slide
-                        // works as usual, otherwise it would think it's a value of 240 + x
-                        if (vol_param & 0xF0) == 0xF0 {
-                            vol_param = vol_param & 0x0F
-                        }
+                    let multiplier = if (pitch_param & 0xF0) == 0xE0 { 1.0 / 4.0 } else { 1.0 };
 
-                        // D0y decreases volume by y units.
-                        // Dx0 increases volume by x units.
-                        if vol_param < 16 {
-                            volume -= vol_param as i32;
-                        } else {
-                            volume += vol_param as i32 / 16;
-                        }
+                    if (pitch_param & 0xF0) == 0xF0 {
+                        pitch_param &= 0xF;
+                    } else if (pitch_param & 0xF0) == 0xE0 {
+                        pitch_param &= 0xF;
+                    }
 
-                        // Volume cannot exceed 64.
-                        channel.note_volume = volume.clamp(0, 64) as u8;
+                    channel.properties.speed *= f64::powf(2.0, -4.0 * (pitch_param as f64 * multiplier) / 768.0);
+                    self.mixer.set_channel_properties(c, channel.mix_properties(self.solo_active));
+                },
+                Effect::PortamentoUp(value) => {
+                    let mut pitch_param = if value == 0 { channel.pitch_memory } else { value };
+                    channel.pitch_memory = pitch_param;
 
-                        let sample = &self.track.samples[sample_id as usize];
-                        channel.properties.volume = ((channel.note_volume as u32 * sample.global_volume as u32 * 64 * self.global_volume as u32) >> 18) as f64 / 128.0 * (self.track.mix_volume as f64 / u8::MAX as f64);
-                        self.system.set_channel_properties(c, channel.properties).unwrap();
-                    },
-                    Effect::PortamentoDown(value) => {
-                        let mut pitch_param = if value == 0 { channel.pitch_memory } else { value };
-                        channel.pitch_memory = pitch_param;
+                    if ((pitch_param & 0xF0) >= 0xE0 && self.current_tick != 0) || self.current_tick == 0 && (pitch_param & 0xF0) < 0xE0 {
+                        continue;
+                    }
 
-                        if ((pitch_param & 0xF0) >= 0xE0 && self.current_tick != 0) || self.current_tick == 0 && (pitch_param & 0xF0) < 0xE0 {
-                            continue;
-                        }
+                    let multiplier = if (pitch_param & 0xF0) == 0xE0 { 1.0 / 4.0 } else { 1.0 };
 
-                        let multiplier = if (pitch_param & 0xF0) == 0xE0 { 1.0 / 4.0 } else { 1.0 };
+                    if (pitch_param & 0xF0) == 0xF0 {
+                        pitch_param &= 0xF;
+                    } else if (pitch_param & 0xF0) == 0xE0 {
+                        pitch_param &= 0xF;
+                    }
 
-                        if (pitch_param & 0xF0) == 0xF0 {
-                            pitch_param &= 0xF;
-                        } else if (pitch_param & 0xF0) == 0xE0 {
-                            pitch_param &= 0xF;
+                    channel.properties.speed *= f64::powf(2.0, 4.0 * (pitch_param as f64 * multiplier) / 768.0);
+                    self.mixer.set_channel_properties(c, channel.mix_properties(self.solo_active));
+                },
+                /*Effect::TonePortamento => todo!(),
+                Effect::Vibrato => todo!(),
+                Effect::Tremor => todo!(),
+                Effect::Arpeggio => todo!(),
+                Effect::VolumeSlideVibrato => todo!(),
+                Effect::VolumeSlideTonePortamento => todo!(),
+                Effect::SetChannelVolume => todo!(),
+                Effect::ChannelVolumeSlide => todo!(),*/
+                Effect::SampleOffset(offset) => {
+                    if self.current_tick == 0 {
+                        let offset = if offset == 0 { channel.offset_memory } else { offset };
+                        channel.offset_memory = offset;
+
+                        if note.key != PianoKey::None {
+                            let position = offset as usize * 256 + channel.high_offset;
+                            let length = channel.current_sample.map(|sample| self.track.samples[sample as usize].length()).unwrap_or(0);
+
+                            // Offsets past the end of the sample just cut the note.
+                            if position < length {
+                                self.mixer.seek_to_sample(c, position);
+                                channel.position = position as f64;
+                            } else {
+                                channel.playing = false;
+                                self.mixer.stop(c);
+                            }
                         }
+                    }
+                },
+                /*Effect::PanningSlide => todo!(),
+                Effect::Retrigger => todo!(),
+                Effect::Tremolo => todo!(),*/
+                Effect::Special(cmd) => {
+                    if cmd >= 0x80 && cmd <= 0x8F {
+                        channel.properties.panning = (cmd & 0xF) as f64 / 15.0;
+                        self.mixer.set_channel_properties(c, channel.mix_properties(self.solo_active));
+                    }
 
-                        channel.properties.speed *= f64::powf(2.0, -4.0 * (pitch_param as f64 * multiplier) / 768.0);
-                        self.system.set_channel_properties(c, channel.properties).unwrap();
-                    },
-                    Effect::PortamentoUp(value) => {
-                        let mut pitch_param = if value == 0 { channel.pitch_memory } else { value };
-                        channel.pitch_memory = pitch_param;
-
-                        if ((pitch_param & 0xF0) >= 0xE0 && self.current_tick != 0) || self.current_tick == 0 && (pitch_param & 0xF0) < 0xE0 {
-                            continue;
-                        }
+                    if cmd >= 0xA0 && cmd <= 0xAF {
+                        channel.high_offset = (cmd & 0xF) as usize * 65536;
+                    }
 
-                        let multiplier = if (pitch_param & 0xF0) == 0xE0 { 1.0 / 4.0 } else { 1.0 };
+                    if cmd >= 0xF0 {
+                        channel.active_macro = cmd & 0xF;
+                    }
 
-                        if (pitch_param & 0xF0) == 0xF0 {
-                            pitch_param &= 0xF;
-                        } else if (pitch_param & 0xF0) == 0xE0 {
-                            pitch_param &= 0xF;
-                        }
+                    if self.current_tick != 0 {
+                        continue;
+                    }
 
-                        channel.properties.speed *= f64::powf(2.0, 4.0 * (pitch_param as f64 * multiplier) / 768.0);
-                        self.system.set_channel_properties(c, channel.properties).unwrap();
-                    },
-                    /*Effect::TonePortamento => todo!(),
-                    Effect::Vibrato => todo!(),
-                    Effect::Tremor => todo!(),
-                    Effect::Arpeggio => todo!(),
-                    Effect::VolumeSlideVibrato => todo!(),
-                    Effect::VolumeSlideTonePortamento => todo!(),
-                    Effect::SetChannelVolume => todo!(),
-                    Effect::ChannelVolumeSlide => todo!(),*/
-                    Effect::SampleOffset(offset) => {
-                        if self.current_tick == 0 {
-                            let offset = if offset == 0 { channel.offset_memory } else { offset };
-                            channel.offset_memory = offset;
-
-                            if note.key != PianoKey::None {
-                                let _ = self.system.seek_to_sample(c, offset as usize * 256 + channel.high_offset);
+                    let value = cmd & 0xF;
+                    match cmd & 0xF0 {
+                        // SB0 sets the loop start, SBx loops back to it x times.
+                        0xB0 => {
+                            if value == 0 {
+                                channel.pattern_loop_start = self.current_row;
+                            } else if channel.pattern_loop_count == 0 {
+                                channel.pattern_loop_count = value;
+                                self.loop_row = Some(channel.pattern_loop_start);
+                            } else {
+                                channel.pattern_loop_count -= 1;
+                                if channel.pattern_loop_count > 0 {
+                                    self.loop_row = Some(channel.pattern_loop_start);
+                                } else {
+                                    channel.pattern_loop_start = self.current_row + 1;
+                                }
                             }
+                        },
+
+                        // SEx repeats the row x times, only the first one in a row counts.
+                        0xE0 => if self.row_delay.is_none() { self.row_delay = Some(value) },
+
+                        // S6x adds x ticks to the row.
+                        0x60 => self.tick_delay += value,
+
+                        _ => {}
+                    }
+                },
+                Effect::Tempo(tempo) => {
+                    if self.current_tick == 0 {
+                        // T0x slides the tempo down, T1x slides it up, anything else sets it.
+                        match tempo & 0xF0 {
+                            0x00 => self.tempo_slide -= (tempo & 0xF) as i32,
+                            0x10 => self.tempo_slide += (tempo & 0xF) as i32,
+                            _ => self.set_tempo(tempo)
                         }
-                    },
-                    /*Effect::PanningSlide => todo!(),
-                    Effect::Retrigger => todo!(),
-                    Effect::Tremolo => todo!(),*/
-                    Effect::Special(cmd) => {
-                        if cmd >= 0x80 && cmd <= 0x8F {
-                            channel.properties.panning = (cmd & 0xF) as f64 / 15.0;
-                            self.system.set_channel_properties(c, channel.properties).unwrap();
-                        }
+                    }
+                },
+                //Effect::FineVibrato => todo!(),
+                Effect::SetGlobalVolume(vol) => {
+                    // TODO: This has weird behaviour right now. When global volume is adjusted - all sample volumes must be
+                    // adjusted too. Currently, this only affects new samples that are played.
+                    self.global_volume = vol;
+                },
+                //Effect::GlobalVolumeSlide => todo!(),
+                Effect::SetPanning(pan) => {
+                    channel.properties.panning = pan as f64 / 255.0;
+                    self.mixer.set_channel_properties(c, channel.mix_properties(self.solo_active));
+                },
+                //Effect::Panbrello => todo!(),
+                Effect::MidiMacro(param) => {
+                    if self.current_tick != 0 {
+                        continue;
+                    }
 
-                        if cmd >= 0xA0 && cmd <= 0xAF {
-                            channel.high_offset = (cmd & 0xF) as usize * 65536;
+                    let midi_macro = self.track.midi_macros.zxx_macro(channel.active_macro, param);
+                    let commands = midi::evaluate_macro(midi_macro, param);
+                    for command in commands.iter() {
+                        match *command {
+                            MacroCommand::FilterCutoff(cutoff) => channel.filter_cutoff = cutoff,
+                            MacroCommand::FilterResonance(resonance) => channel.filter_resonance = resonance
                         }
-                    },
-                    Effect::Tempo(tempo) => {
-                        // TODO: Tempo slides
-                        if tempo > 0x20 && self.current_tick == 0 {
-                            self.set_tempo(tempo);
+                    }
+
+                    // The filter is baked into the buffer, so swap to a buffer with the new filter and carry on
+                    // from where the old one was.
+                    if !commands.is_empty() && channel.playing {
+                        if let Some(sample_id) = channel.current_sample {
+                            let buffer = get_buffer(&self.buffers, &self.filter_buffers, sample_id, channel);
+                            self.mixer.play_buffer(buffer, c, channel.mix_properties(self.solo_active));
+                            self.mixer.seek_to_sample(c, channel.position as usize);
                         }
-                    },
-                    //Effect::FineVibrato => todo!(),
-                    Effect::SetGlobalVolume(vol) => {
-                        // TODO: This has weird behaviour right now. When global volume is adjusted - all sample volumes must be
-                        // adjusted too. Currently, this only affects new samples that are played.
-                        self.global_volume = vol;
-                    },
-                    //Effect::GlobalVolumeSlide => todo!(),
-                    Effect::SetPanning(pan) => {
-                        channel.properties.panning = pan as f64 / 255.0;
-                        self.system.set_channel_properties(c, channel.properties).unwrap();
-                    },
-                    /*Effect::Panbrello => todo!(),
-                    Effect::MidiMacro => todo!(),*/
-                    _ => {}
-                }
+                    }
+                },
+                _ => {}
             }
         }
 
-        self.current_half_sample += 1;
+        // Tempo slides happen on every tick except the first of each time the row is played.
+        if self.tempo_slide != 0 && self.current_tick % self.current_speed as u16 != 0 {
+            self.set_tempo((self.current_tempo as i32 + self.tempo_slide).clamp(32, 255) as u8);
+        }
+    }
 
-        if self.current_half_sample >= self.half_samples_per_tick {
-            self.current_tick += 1;
-            self.current_half_sample = 0;
+    /// Forget the effects of the last row, ready for the next one.
+    fn clear_row_effects(&mut self) {
+        self.break_row = None;
+        self.jump_order = None;
+        self.loop_row = None;
+        self.row_delay = None;
+        self.tick_delay = 0;
+        self.tempo_slide = 0;
+    }
+
+    /// Move on to the next tick, moving to the next row and order where needed.
+    fn next_tick(&mut self) {
+        let rows = self.track.patterns[self.track.orders[self.current_order] as usize].rows;
+
+        self.update_positions();
+
+        self.current_tick += 1;
+
+        // SEx plays the row again x times, and S6x adds ticks on to the end of it.
+        let row_ticks = self.current_speed as u16 * (1 + self.row_delay.unwrap_or(0) as u16) + self.tick_delay as u16;
 
-            if self.current_tick >= self.current_speed {
-                self.current_tick = 0;
-                self.current_row += 1;     
-                
-                if self.should_jump {
-                    self.should_jump = false;
-                    self.current_row = self.next_row;
-                    self.current_order = self.next_order;
+        if self.current_tick >= row_ticks {
+            self.current_tick = 0;
+            let prev_order = self.current_order;
+
+            if let Some(loop_row) = self.loop_row {
+                // Pattern loops win over any breaks or jumps on the same row. The rows inside the loop are about to be
+                // played again, so they shouldn't count as the song looping.
+                for row in loop_row..=self.current_row {
+                    self.visited.remove(&(self.current_order, row));
                 }
 
-                if self.current_row >= pattern.rows as usize {
+                self.current_row = loop_row;
+            } else if self.break_row.is_some() || self.jump_order.is_some() {
+                self.current_row = self.break_row.unwrap_or(0);
+                self.current_order = self.jump_order.unwrap_or(self.current_order + 1);
+            } else {
+                self.current_row += 1;
+                if self.current_row >= rows as usize {
                     self.current_row = 0;
                     self.current_order += 1;
+                }
+            }
 
-                    if self.current_order >= self.track.orders.len() || self.track.orders[self.current_order] == 255 {
-                        if self.looping {
-                            self.current_order = 0;
-                        }
-                        else {
-                            return 0.0;
+            // Skip over any markers or missing patterns. Once there's nothing left to play, the song starts again.
+            match self.find_order(self.current_order) {
+                Some(order) => self.current_order = order,
+                None => {
+                    self.current_row = 0;
+                    self.song_looped();
+                    self.reset_pattern_loops();
+                    self.visited.clear();
+
+                    // Positions after the end of the song can still be seeked to, so there might not be a start to
+                    // go back to.
+                    match self.find_order(0) {
+                        Some(order) => self.current_order = order,
+                        None => {
+                            self.state = PlaybackState::Finished;
+                            return;
                         }
                     }
                 }
+            }
 
-                //println!("Ord {}/{} Row {}/{} Spd {}, HSPT {} (Tmp {}, SR {})", self.current_order + 1, self.track.orders.len(), self.current_row, pattern.rows, self.current_speed, self.half_samples_per_tick, self.current_tempo, SAMPLE_RATE);
+            // Pattern breaks can point past the end of the next pattern, in which case we start from the top.
+            if self.current_row >= self.track.patterns[self.track.orders[self.current_order] as usize].rows as usize {
+                self.current_row = 0;
             }
+
+            // Pattern loops don't carry over between orders, see `calculate_length`.
+            if self.current_order != prev_order {
+                self.reset_pattern_loops();
+            }
+
+            // Jumping back somewhere we've already been means the song is going to repeat itself from here.
+            if !self.visited.insert((self.current_order, self.current_row)) {
+                self.song_looped();
+                self.visited.clear();
+                self.visited.insert((self.current_order, self.current_row));
+            }
+
+            //println!("Ord {}/{} Row {}/{} Spd {}, HSPT {} (Tmp {}, SR {})", self.current_order + 1, track.orders.len(), self.current_row, pattern.rows, self.current_speed, self.half_samples_per_tick, self.current_tempo, self.sample_rate);
         }
+    }
+
+    fn reset_pattern_loops(&mut self) {
+        for channel in self.channels.iter_mut() {
+            channel.pattern_loop_start = 0;
+            channel.pattern_loop_count = 0;
+        }
+    }
+
+    fn song_looped(&mut self) {
+        self.loop_count += 1;
+        self.looped = true;
+
+        self.events.push(self.elapsed_half_samples / 2, PlayerEvent::SongLooped { count: self.loop_count });
+
+        self.state = match self.max_loops {
+            Some(max_loops) if self.loop_count > max_loops => PlaybackState::Finished,
+            _ => PlaybackState::Looped(self.loop_count)
+        };
+    }
+
+    /// Get a handle that can control this player from another thread.
+    pub fn handle(&self) -> PlayerHandle {
+        PlayerHandle::new(self.command_sender.clone())
+    }
 
-        self.system.advance()
+    /// Pause or resume playback. While paused, the player outputs silence.
+    pub fn set_paused(&mut self, paused: bool) {
+        self.paused = paused;
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    /// Stop playback entirely. Seeking will start it again.
+    pub fn stop(&mut self) {
+        for c in 0..self.channels.len() {
+            self.channels[c].playing = false;
+            self.mixer.stop(c as u16);
+        }
+
+        self.state = PlaybackState::Finished;
+    }
+
+    pub fn state(&self) -> PlaybackState {
+        self.state
+    }
+
+    /// Set the number of times the song will loop before finishing. `None` will loop forever, `Some(0)` plays once.
+    pub fn set_max_loops(&mut self, max_loops: Option<u32>) {
+        self.max_loops = max_loops;
+    }
+
+    /// Returns true if the song has looped since the last time this was called.
+    pub fn take_looped(&mut self) -> bool {
+        std::mem::take(&mut self.looped)
+    }
+
+    pub fn set_channel_muted(&mut self, channel: u16, muted: bool) {
+        if let Some(track_channel) = self.channels.get_mut(channel as usize) {
+            track_channel.muted = muted;
+            self.update_mix_properties();
+        }
+    }
+
+    /// Solo the given channel. While any channel is soloed, only soloed channels can be heard.
+    pub fn set_channel_solo(&mut self, channel: u16, solo: bool) {
+        if let Some(track_channel) = self.channels.get_mut(channel as usize) {
+            track_channel.solo = solo;
+            self.solo_active = self.channels.iter().any(|c| c.solo);
+            self.update_mix_properties();
+        }
+    }
+
+    pub fn channel_states(&self) -> Vec<ChannelState> {
+        self.channels.iter().filter(|channel| !channel.live).map(|channel| ChannelState {
+            muted: channel.muted,
+            solo: channel.solo,
+            audible: channel.audible(self.solo_active),
+
+            playing: channel.playing,
+            sample: channel.current_sample,
+            volume: channel.note_volume,
+            position: channel.position
+        }).collect()
+    }
+
+    fn update_mix_properties(&mut self) {
+        for (c, channel) in self.channels.iter().enumerate() {
+            self.mixer.set_channel_properties(c as u16, channel.mix_properties(self.solo_active));
+        }
+    }
+
+    /// Enable or disable rendering each channel to its own stem. This keeps an extra copy of every sample for each
+    /// channel, so should only really be used for offline rendering.
+    pub fn set_stems_enabled(&mut self, enabled: bool) {
+        let stems = if enabled { self.track.num_channels() as usize } else { 0 };
+        if stems == self.mixer.num_stems() {
+            return;
+        }
+
+        self.mixer = Mixer::new(self.sample_rate, self.mixer.num_voices(), stems);
+        self.buffers = create_buffers(&mut self.mixer, &self.track);
+        self.filter_buffers = create_filter_buffers(&mut self.mixer, &self.track);
+
+        // The new mixer has nothing playing, so replay up to where we are now to get everything back.
+        self.seek_seconds(self.elapsed_seconds());
+    }
+
+    /// Get the number of stems that will be rendered by [`TrackPlayer::render_stems`].
+    pub fn num_stems(&self) -> usize {
+        self.mixer.num_stems()
+    }
+
+    /// Start or stop collecting events. Events are only collected while enabled, and should be drained regularly.
+    pub fn set_events_enabled(&mut self, enabled: bool) {
+        self.events.enabled = enabled;
+        if !enabled {
+            self.events.events.clear();
+        }
+    }
+
+    /// Take all events that have happened since the last time this was called, in the order they happened.
+    pub fn drain_events(&mut self) -> std::vec::Drain<'_, TimedEvent> {
+        self.events.events.drain(..)
     }
 
     pub fn set_interpolation(&mut self, interp_type: mixr::InterpolationType) {
@@ -366,40 +1025,258 @@ impl<'a> TrackPlayer<'a> {
         self.set_tempo(self.current_tempo);
     }
 
+    /// Seek to the tick closest to the given time in seconds, returning the time that was actually seeked to.
+    /// Seeking past the end of the song stops where it ends, or where it loops back if it loops forever.
     pub fn seek_seconds(&mut self, seconds: f64) -> f64 {
-        for i in 0..self.track.seek_table.len() {
-            let table = &self.track.seek_table[i];
-            if table.start > seconds {
-                let table = &self.track.seek_table[i - 1];
-                for j in 0..table.rows.len() {
-                    let row = &table.rows[j];
-                    if row.start > seconds {
-                        self.current_tick = 0;
-                        self.current_half_sample = 0;
-                        self.current_order = i - if i == 0 { 0 } else { 1 };
-                        
-                        self.current_row = j - if j == 0 { 0 } else { 1 };
-
-                        self.current_speed = row.speed;
-                        self.set_tempo(row.tempo);
-
-                        return row.start;
+        let target = (seconds * self.sample_rate as f64 * 2.0) as u64;
+        self.replay(|player| player.elapsed_half_samples + player.half_samples_per_tick as u64 > target);
+
+        self.elapsed_seconds()
+    }
+
+    /// Seek to the start of the given row in the given order. If the position can't be reached by playing through the
+    /// track, playback starts from there directly. Returns false if the position does not exist.
+    pub fn seek_position(&mut self, order: usize, row: usize) -> bool {
+        match self.track.orders.get(order) {
+            Some(pattern) if (*pattern as usize) < self.track.patterns.len() && row < self.track.patterns[*pattern as usize].rows as usize => {},
+            _ => return false
+        }
+
+        // Every reachable position will have been played before the song loops for the first time.
+        self.replay(|player| (player.current_order == order && player.current_row == row && player.current_tick == 0) || player.loop_count > 0);
+
+        if self.current_order != order || self.current_row != row {
+            self.reset();
+            self.current_order = order;
+            self.current_row = row;
+            self.state = PlaybackState::Playing;
+
+            self.visited.clear();
+            self.visited.insert((order, row));
+        }
+
+        true
+    }
+
+    /// Get the current order, row, and tick.
+    pub fn position(&self) -> (usize, usize, u16) {
+        (self.current_order, self.current_row, self.current_tick)
+    }
+
+    /// Get the amount of time that has been played so far, in seconds.
+    pub fn elapsed_seconds(&self) -> f64 {
+        self.elapsed_half_samples as f64 / 2.0 / self.sample_rate as f64
+    }
+
+    /// Play the track silently from the start until the given condition is met, so that every channel, volume and
+    /// effect memory ends up exactly as it would have been had the track been played through normally.
+    fn replay(&mut self, done: impl Fn(&Self) -> bool) {
+        self.reset();
+
+        // Nothing is actually heard during the replay, so there's no point reporting events for it.
+        let events_enabled = std::mem::replace(&mut self.events.enabled, false);
+
+        // Without a loop limit the song never finishes, so stop once it loops rather than replaying forever.
+        while !done(self) && self.state != PlaybackState::Finished && !(self.max_loops.is_none() && self.loop_count > 0) {
+            self.process_tick();
+            self.elapsed_half_samples += self.half_samples_per_tick as u64;
+            self.next_tick();
+        }
+
+        // Nothing has actually been mixed, so pick up every note that is still playing from where it would be now.
+        for c in 0..self.channels.len() {
+            let channel = &self.channels[c];
+            if channel.playing {
+                self.mixer.seek_to_sample(c as u16, channel.position as usize);
+            } else {
+                self.mixer.stop(c as u16);
+            }
+        }
+
+        self.looped = false;
+        self.events.enabled = events_enabled;
+
+        // Make sure whoever is listening finds out where we ended up.
+        self.event_order = None;
+    }
+
+    /// Reset the player back to the start of the track.
+    fn reset(&mut self) {
+        for (i, channel) in self.channels.iter_mut().enumerate() {
+            let (muted, solo) = (channel.muted, channel.solo);
+
+            *channel = TrackChannel::new(channel_pan(&self.track, i as u16), channel.properties.interpolation, channel.live);
+            channel.muted = muted;
+            channel.solo = solo;
+            self.mixer.stop(i as u16);
+        }
+
+        self.current_half_sample = 0;
+        self.elapsed_half_samples = 0;
+        self.current_tick = 0;
+        self.current_speed = self.track.speed.max(1);
+        self.set_tempo(self.track.tempo);
+
+        self.current_order = 0;
+        self.current_row = 0;
+        self.clear_row_effects();
+
+        self.global_volume = self.track.global_volume;
+
+        // A track with nothing to play finishes straight away.
+        self.state = match self.find_order(0) {
+            Some(order) => {
+                self.current_order = order;
+                PlaybackState::Playing
+            },
+            None => PlaybackState::Finished
+        };
+        self.loop_count = 0;
+        self.looped = false;
+
+        self.visited.clear();
+        self.visited.insert((self.current_order, self.current_row));
+
+        self.event_order = None;
+    }
+
+    /// Keep track of roughly where each channel is in its sample, so the buffer can be swapped without restarting it.
+    fn update_positions(&mut self) {
+        let frames = self.half_samples_per_tick as f64 / 2.0;
+
+        for channel in self.channels.iter_mut() {
+            if !channel.playing {
+                continue;
+            }
+
+            if let Some(sample_id) = channel.current_sample {
+                let sample = &self.track.samples[sample_id as usize];
+                channel.position += channel.properties.speed * (sample.format.sample_rate as f64 / self.sample_rate as f64) * frames;
+
+                if sample.looping && sample.loop_end > sample.loop_start {
+                    let (start, end) = (sample.loop_start as f64, sample.loop_end as f64);
+                    if channel.position >= end {
+                        channel.position = start + (channel.position - start) % (end - start);
                     }
+                } else if channel.position >= sample.length() as f64 {
+                    channel.playing = false;
                 }
             }
         }
+    }
+
+    pub fn track(&self) -> &Track {
+        &self.track
+    }
 
-        0.0
+    pub fn sample_rate(&self) -> i32 {
+        self.sample_rate
     }
 
     fn set_tempo(&mut self, tempo: u8) {
         self.current_tempo = tempo;
-        self.half_samples_per_tick = (calculate_half_samples_per_tick(tempo) as f64 * (1.0 / self.tempo_tuning)) as u32;
+
+        // A tick must always take some time, otherwise rendering would never get anywhere.
+        self.half_samples_per_tick = ((calculate_half_samples_per_tick(tempo, self.sample_rate) as f64 * (1.0 / self.tempo_tuning)) as u32).max(1);
+    }
+
+    /// Find the first order at or after the given one that can be played, or None if the song ends first.
+    fn find_order(&self, order: usize) -> Option<usize> {
+        crate::track::next_valid_order(&self.track.patterns, &self.track.orders, order)
+    }
+}
+
+/// Get the initial pan of the given voice. Voices past the end of the track's channels are centered.
+fn channel_pan(track: &Track, voice: u16) -> u8 {
+    if voice < track.num_channels() {
+        track.pans.get(voice as usize).copied().unwrap_or(32)
+    } else {
+        32
     }
 }
 
-pub fn calculate_half_samples_per_tick(tempo: u8) -> u32 {
-    ((2.5 / tempo as f64) * 2.0 * SAMPLE_RATE as f64) as u32
+fn create_buffers(mixer: &mut Mixer, track: &Track) -> Vec<usize> {
+    track.samples.iter().map(|sample| {
+        mixer.create_buffer(BufferDescription { data_type: DataType::Pcm, format: sample.format }, &sample.data)
+    }).collect()
+}
+
+/// Create filtered copies of samples for every filter each sample is played with in the track, so they don't have to
+/// be made while playing. The copies are filtered as if the sample is played at its own sample rate, so the filter
+/// follows the pitch of the note rather than staying at a fixed frequency.
+fn create_filter_buffers(mixer: &mut Mixer, track: &Track) -> HashMap<(u8, u8, u8), usize> {
+    // (sample, cutoff, resonance, active macro) for each channel, which is all that's needed to know what gets filtered.
+    let mut states = vec![(None, 127, 0, 0); track.num_channels() as usize];
+    let mut filters = Vec::new();
+
+    for (_, _, channel, note) in track.iter_song() {
+        let (sample, cutoff, resonance, active_macro) = &mut states[channel as usize];
+
+        if let (Some(id), Some(_)) = (note.sample, note.key.semitone()) {
+            *sample = Some(id);
+        }
+
+        // The note is triggered with the old filter before any Zxx on the same row changes it.
+        filters.push((*sample, *cutoff, *resonance));
+
+        match note.effect {
+            Effect::Special(cmd) if cmd >= 0xF0 => *active_macro = cmd & 0xF,
+            Effect::MidiMacro(param) => {
+                for command in midi::evaluate_macro(track.midi_macros.zxx_macro(*active_macro, param), param) {
+                    match command {
+                        MacroCommand::FilterCutoff(value) => *cutoff = value,
+                        MacroCommand::FilterResonance(value) => *resonance = value
+                    }
+                }
+
+                filters.push((*sample, *cutoff, *resonance));
+            },
+            _ => {}
+        }
+    }
+
+    let mut filter_buffers = HashMap::new();
+    for (sample, cutoff, resonance) in filters {
+        let Some(sample_id) = sample.filter(|id| (*id as usize) < track.samples.len()) else {
+            continue;
+        };
+
+        // Much like IT, the filter is bypassed entirely if it wouldn't do anything.
+        let key = (sample_id, cutoff, resonance);
+        if (cutoff >= 127 && resonance == 0) || filter_buffers.contains_key(&key) {
+            continue;
+        }
+
+        if filter_buffers.len() >= MAX_FILTER_BUFFERS {
+            log::warn!("Track uses more than {MAX_FILTER_BUFFERS} filters, some will be ignored.");
+            break;
+        }
+
+        let sample = &track.samples[sample_id as usize];
+        let data = sample.filtered(midi::cutoff_to_frequency(cutoff), resonance);
+
+        let mut format = sample.format;
+        format.format_type = FormatType::I16;
+
+        filter_buffers.insert(key, mixer.create_buffer(BufferDescription { data_type: DataType::Pcm, format }, &data));
+    }
+
+    filter_buffers
+}
+
+/// Get the buffer a sample should be played with on the given channel. If the channel's filter is active, the filtered
+/// copy made by [`create_filter_buffers`] is used, or the unfiltered sample if there isn't one.
+fn get_buffer(buffers: &[usize], filter_buffers: &HashMap<(u8, u8, u8), usize>, sample_id: u8, channel: &TrackChannel) -> usize {
+    let key = (sample_id, channel.filter_cutoff, channel.filter_resonance);
+    filter_buffers.get(&key).copied().unwrap_or(buffers[sample_id as usize])
+}
+
+pub fn calculate_half_samples_per_tick(tempo: u8, sample_rate: i32) -> u32 {
+    ((2.5 / tempo.max(1) as f64) * 2.0 * sample_rate as f64) as u32
+}
+
+pub fn calculate_volume(volume: u8, sample: &Sample, global_volume: u8, mix_volume: u8) -> f64 {
+    ((volume as u32 * sample.global_volume as u32 * 64 * global_volume as u32) >> 18) as f64 / 128.0 * (mix_volume as f64 / u8::MAX as f64)
 }
 
 pub fn calculate_speed(key: PianoKey, octave: u8, multiplier: f64) -> f64 {
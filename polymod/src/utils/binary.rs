@@ -0,0 +1,108 @@
+use crate::Error;
+
+/// Reads little-endian data, returning an error instead of panicking if a read goes past the end of the data.
+pub struct BinaryReader<'a> {
+    data: &'a [u8],
+    pub position: usize
+}
+
+impl<'a> BinaryReader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { data, position: 0 }
+    }
+
+    /// Move to the given position, which must be within the data.
+    pub fn seek(&mut self, position: usize) -> Result<(), Error> {
+        if position > self.data.len() {
+            return Err(Error::InvalidOffset(position));
+        }
+
+        self.position = position;
+        Ok(())
+    }
+
+    pub fn read_bytes(&mut self, count: usize) -> Result<&'a [u8], Error> {
+        let end = self.position.checked_add(count).filter(|end| *end <= self.data.len()).ok_or(Error::TruncatedFile)?;
+        let bytes = &self.data[self.position..end];
+        self.position = end;
+
+        Ok(bytes)
+    }
+
+    pub fn read_u8(&mut self) -> Result<u8, Error> {
+        Ok(self.read_bytes(1)?[0])
+    }
+
+    pub fn read_u16(&mut self) -> Result<u16, Error> {
+        Ok(u16::from_le_bytes(self.read_bytes(2)?.try_into().unwrap()))
+    }
+
+    pub fn read_u32(&mut self) -> Result<u32, Error> {
+        Ok(u32::from_le_bytes(self.read_bytes(4)?.try_into().unwrap()))
+    }
+
+    pub fn read_i32(&mut self) -> Result<i32, Error> {
+        Ok(i32::from_le_bytes(self.read_bytes(4)?.try_into().unwrap()))
+    }
+
+    /// Read a fixed length string. Anything after the first null is ignored.
+    pub fn read_string(&mut self, length: usize) -> Result<String, Error> {
+        let bytes = self.read_bytes(length)?;
+        let end = bytes.iter().position(|b| *b == 0).unwrap_or(bytes.len());
+
+        Ok(String::from_utf8_lossy(&bytes[..end]).to_string())
+    }
+}
+
+/// Writes little-endian data, growing as needed. Writing somewhere other than the end overwrites what is there.
+#[derive(Default)]
+pub struct BinaryWriter {
+    data: Vec<u8>,
+    position: usize,
+}
+
+impl BinaryWriter {
+    pub fn new() -> Self {
+        Self {
+            data: Vec::new(),
+            position: 0
+        }
+    }
+
+    pub fn write_u8(&mut self, value: u8) {
+        self.write_bytes(&[value]);
+    }
+
+    pub fn write_u16(&mut self, value: u16) {
+        self.write_bytes(&value.to_le_bytes());
+    }
+
+    pub fn write_u32(&mut self, value: u32) {
+        self.write_bytes(&value.to_le_bytes());
+    }
+
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        self.ensure_size(self.position + bytes.len());
+        self.data[self.position..self.position + bytes.len()].copy_from_slice(bytes);
+        self.position += bytes.len();
+    }
+
+    pub fn get_data(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    pub fn set_position(&mut self, position: usize) {
+        self.ensure_size(position);
+        self.position = position;
+    }
+
+    fn ensure_size(&mut self, size: usize) {
+        if size > self.data.len() {
+            self.data.resize(size, 0);
+        }
+    }
+}
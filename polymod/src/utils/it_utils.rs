@@ -31,4 +31,49 @@ pub fn get_effect(it_effect: u8, param: u8) -> Effect {
         26 => Effect::MidiMacro(param),
         _ => Effect::None
     }
-}
\ No newline at end of file
+}
+
+/// The opposite of [`get_effect`], returning the IT effect number and its parameter.
+pub fn get_it_effect(effect: Effect) -> (u8, u8) {
+    match effect {
+        Effect::None => (0, 0),
+        Effect::SetSpeed(param) => (1, param),
+        Effect::PositionJump(param) => (2, param),
+        Effect::PatternBreak(param) => (3, param),
+        Effect::VolumeSlide(param) => (4, param),
+        Effect::PortamentoDown(param) => (5, param),
+        Effect::PortamentoUp(param) => (6, param),
+        Effect::TonePortamento(param) => (7, param),
+        Effect::Vibrato(param) => (8, param),
+        Effect::Tremor(param) => (9, param),
+        Effect::Arpeggio(param) => (10, param),
+        Effect::VolumeSlideVibrato(param) => (11, param),
+        Effect::VolumeSlideTonePortamento(param) => (12, param),
+        Effect::SetChannelVolume(param) => (13, param),
+        Effect::ChannelVolumeSlide(param) => (14, param),
+        Effect::SampleOffset(param) => (15, param),
+        Effect::PanningSlide(param) => (16, param),
+        Effect::Retrigger(param) => (17, param),
+        Effect::Tremolo(param) => (18, param),
+        Effect::Special(param) => (19, param),
+        Effect::Tempo(param) => (20, param),
+        Effect::FineVibrato(param) => (21, param),
+        Effect::SetGlobalVolume(param) => (22, param),
+        Effect::GlobalVolumeSlide(param) => (23, param),
+        Effect::SetPanning(param) => (24, param),
+        Effect::Panbrello(param) => (25, param),
+        Effect::MidiMacro(param) => (26, param)
+    }
+}
+
+/// Get the name of the tracker from the "created with" field in the header.
+pub fn get_tracker_name(cwt: u16) -> String {
+    let version = format!("{:X}.{:02X}", (cwt >> 8) & 0xF, cwt & 0xFF);
+
+    match cwt >> 12 {
+        0 => format!("Impulse Tracker {version}"),
+        1 => String::from("Schism Tracker"),
+        5 => String::from("OpenMPT"),
+        _ => format!("Unknown ({cwt:#06X})")
+    }
+}
@@ -1 +1,2 @@
-pub mod it_utils;
\ No newline at end of file
+pub mod it_utils;
+pub mod binary;
\ No newline at end of file
@@ -0,0 +1,92 @@
+use mixr::{AudioFormat, FormatType};
+use polymod::{builder::TrackBuilder, track::Pattern, sample::Sample, Note, PianoKey, Effect, Error};
+
+fn create_sample() -> Sample {
+    let format = AudioFormat { format_type: FormatType::I8, channels: 1, sample_rate: 8363, ..AudioFormat::default() };
+
+    Sample::new(&[0x40; 32], format, true, 0, 32, 64, 64)
+}
+
+fn create_pattern(sample: u8) -> Pattern {
+    let mut pattern = Pattern::new(4, 16);
+    pattern.set_note(0, 0, Note::new(PianoKey::C, 5, Some(sample), None, Effect::None));
+    pattern.set_note(1, 8, Note::new(PianoKey::G, 5, Some(sample), Some(32), Effect::None));
+
+    pattern
+}
+
+#[test]
+fn test_build() {
+    let track = TrackBuilder::new()
+        .sample(create_sample())
+        .pattern(create_pattern(0))
+        .orders(&[0, 254, 0])
+        .tempo(150)
+        .speed(3)
+        .pan(1, 0)
+        .build()
+        .unwrap();
+
+    assert_eq!(track.num_channels(), 4);
+    assert_eq!(track.pans, vec![32, 0, 32, 32]);
+    assert_eq!(track.metadata.samples.len(), 1);
+
+    // 2 orders of 16 rows, at 3 ticks per row and 150 BPM.
+    assert!((track.length_in_seconds - 32.0 * 3.0 * 2.5 / 150.0).abs() < 0.0001);
+    assert_eq!(track.seek_table.len(), 2);
+    assert_eq!(track.loop_start, None);
+}
+
+#[test]
+fn test_build_invalid() {
+    let result = TrackBuilder::new().sample(create_sample()).pattern(create_pattern(0)).orders(&[0, 1]).build();
+    assert!(matches!(result, Err(Error::MissingPattern { order: 1, pattern: 1 })));
+
+    let result = TrackBuilder::new().sample(create_sample()).pattern(create_pattern(3)).order(0).build();
+    assert!(matches!(result, Err(Error::MissingSample { pattern: 0, row: 0, channel: 0, sample: 3 })));
+
+    let result = TrackBuilder::new().pattern(Pattern::new(4, 16)).order(0).tempo(10).build();
+    assert!(matches!(result, Err(Error::OutOfRange { name: "tempo", value: 10 })));
+}
+
+#[test]
+fn test_build_invalid_samples() {
+    let mut sample = create_sample();
+    sample.format.sample_rate = 0;
+    let result = TrackBuilder::new().sample(sample).pattern(create_pattern(0)).order(0).build();
+    assert!(matches!(result, Err(Error::OutOfRange { name: "sample rate", value: 0 })));
+
+    let mut sample = create_sample();
+    sample.loop_start = 10;
+    sample.loop_end = 5;
+    let result = TrackBuilder::new().sample(sample).pattern(create_pattern(0)).order(0).build();
+    assert!(matches!(result, Err(Error::InvalidLoop { sample: 0, start: 10, end: 5 })));
+
+    let mut sample = create_sample();
+    sample.loop_end = 33;
+    let result = TrackBuilder::new().sample(sample).pattern(create_pattern(0)).order(0).build();
+    assert!(matches!(result, Err(Error::InvalidLoop { .. })));
+}
+
+#[test]
+fn test_build_special_pans() {
+    // Surround and disabled channels are valid, just not anything else above 64.
+    let track = TrackBuilder::new().pattern(Pattern::new(3, 16)).order(0).pan(0, 100).pan(1, 128 + 32).build().unwrap();
+    assert_eq!(track.pans, vec![100, 160, 32]);
+
+    let result = TrackBuilder::new().pattern(Pattern::new(3, 16)).order(0).pan(0, 65).build();
+    assert!(matches!(result, Err(Error::OutOfRange { name: "pan", value: 65 })));
+}
+
+#[test]
+fn test_build_volume_column() {
+    // Anything the IT volume column can hold is fine.
+    let mut pattern = create_pattern(0);
+    pattern.set_note(0, 1, Note::new(PianoKey::C, 5, Some(0), Some(212), Effect::None));
+    assert!(TrackBuilder::new().sample(create_sample()).pattern(pattern).order(0).build().is_ok());
+
+    let mut pattern = create_pattern(0);
+    pattern.set_note(0, 1, Note::new(PianoKey::C, 5, Some(0), Some(213), Effect::None));
+    let result = TrackBuilder::new().sample(create_sample()).pattern(pattern).order(0).build();
+    assert!(matches!(result, Err(Error::OutOfRange { name: "note volume", value: 213 })));
+}
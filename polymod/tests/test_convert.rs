@@ -0,0 +1,78 @@
+use mixr::{AudioFormat, FormatType};
+use polymod::{builder::TrackBuilder, convert::{self, ConversionWarning}, track::{Track, Pattern}, sample::Sample, Note, PianoKey, Effect, ModuleType, Error};
+
+fn create_track(channels: u16, rows: u16) -> Track {
+    let mut pattern = Pattern::new(channels, rows);
+    pattern.set_note(0, 0, Note::new(PianoKey::C, 5, Some(0), None, Effect::MidiMacro(0x10)));
+    pattern.set_note(1, 0, Note::new(PianoKey::C, 5, Some(0), None, Effect::FineVibrato(0x48)));
+    pattern.set_note(2, 1, Note::new(PianoKey::E, 5, Some(0), None, Effect::SetSpeed(3)));
+
+    let format = AudioFormat { format_type: FormatType::I16, channels: 2, sample_rate: 8363, ..AudioFormat::default() };
+
+    // Stereo samples are stored one channel after the other, this is full volume on the left and silence on the right.
+    let mut data = [0x00, 0x40].repeat(16);
+    data.extend_from_slice(&[0; 32]);
+
+    TrackBuilder::new()
+        .mod_type(ModuleType::IT)
+        .pattern(pattern)
+        .orders(&[0, 255])
+        .sample(Sample::new(&data, format, false, 0, -1, 64, 64))
+        .build()
+        .unwrap()
+}
+
+#[test]
+fn test_convert_to_mod() {
+    let track = create_track(40, 32);
+    let conversion = convert::convert(&track, ModuleType::MOD);
+    let converted = &conversion.track;
+
+    assert_eq!(converted.mod_type, ModuleType::MOD);
+    assert!(conversion.warnings.contains(&ConversionWarning::TooManyChannels { channels: 40, max: 32 }));
+    assert!(conversion.warnings.contains(&ConversionWarning::UnsupportedEffect { pattern: 0, row: 0, channel: 0, effect: Effect::MidiMacro(0x10) }));
+    assert!(conversion.warnings.contains(&ConversionWarning::ApproximatedEffect { pattern: 0, row: 0, channel: 1, from: Effect::FineVibrato(0x48), to: Effect::Vibrato(0x42) }));
+    assert!(conversion.warnings.contains(&ConversionWarning::PatternResized { pattern: 0, rows: 32, new_rows: 64 }));
+    assert!(conversion.warnings.contains(&ConversionWarning::SampleConverted { sample: 0, sixteen_bit: false, channels: 1 }));
+
+    let pattern = &converted.patterns[0];
+    assert_eq!((pattern.channels, pattern.rows), (32, 64));
+    assert_eq!(pattern.notes.get(0, 0).effect, Effect::None);
+    assert_eq!(pattern.notes.get(0, 0).key, PianoKey::C);
+    assert_eq!(pattern.notes.get(0, 31).effect, Effect::PatternBreak(0));
+
+    // The padding is skipped, so the song is just as long as it was: one row at speed 6, then 31 at speed 3.
+    assert!((converted.length_in_seconds - (6.0 + 31.0 * 3.0) * 2.5 / 125.0).abs() < 0.0001);
+
+    // Mixed down to mono, the left channel at full volume ends up at half volume.
+    let sample = &converted.samples[0];
+    assert_eq!((sample.format.channels, sample.format.bytes_per_sample()), (1, 1));
+    assert_eq!(sample.data, vec![32; 16]);
+}
+
+#[test]
+fn test_convert_to_it() {
+    // Everything fits in IT, so nothing should change.
+    let track = create_track(4, 32);
+    let conversion = convert::convert(&track, ModuleType::IT);
+    assert!(conversion.warnings.is_empty());
+    assert_eq!(conversion.track.samples[0].data, track.samples[0].data);
+
+    let data = convert::save(&conversion.track, ModuleType::IT).unwrap();
+    assert!(convert::load(&data, ModuleType::IT).is_ok());
+
+    assert!(matches!(convert::save(&conversion.track, ModuleType::XM), Err(Error::Unsupported(_))));
+    assert_eq!(ModuleType::from_extension("S3M"), Some(ModuleType::S3M));
+}
+
+#[test]
+fn test_break_not_added() {
+    // The only channel already has an effect on the last row, so there's nowhere to put the break.
+    let mut pattern = Pattern::new(1, 32);
+    pattern.set_note(0, 31, Note::new(PianoKey::None, 0, None, None, Effect::SetSpeed(3)));
+    let track = TrackBuilder::new().pattern(pattern).order(0).build().unwrap();
+
+    let conversion = convert::convert(&track, ModuleType::S3M);
+    assert!(conversion.warnings.contains(&ConversionWarning::BreakNotAdded { pattern: 0, row: 31 }));
+    assert_eq!(conversion.track.patterns[0].rows, 64);
+}
@@ -0,0 +1,100 @@
+use polymod::{builder::TrackBuilder, edit::{Edit, History}, track::{Pattern, Selection}, Note, PianoKey, Effect, Error};
+
+fn create_pattern() -> Pattern {
+    let mut pattern = Pattern::new(4, 8);
+    for row in 0..8 {
+        pattern.set_note(0, row, Note::new(PianoKey::C, row as u8, None, None, Effect::None));
+    }
+
+    pattern.set_note(1, 0, Note::new(PianoKey::B, 9, None, None, Effect::None));
+
+    pattern
+}
+
+#[test]
+fn test_pattern_edits() {
+    let mut pattern = create_pattern();
+
+    pattern.insert_rows(2, 2).unwrap();
+    assert_eq!(pattern.rows, 10);
+    assert!(!pattern.note(0, 2).unwrap().initialized);
+    assert_eq!(pattern.note(0, 4).unwrap().octave, 2);
+
+    pattern.delete_rows(2, 2).unwrap();
+    assert_eq!(pattern.rows, 8);
+    assert_eq!(pattern.note(0, 2).unwrap().octave, 2);
+
+    // B-9 can't go any higher, so it's left alone.
+    pattern.transpose(Selection::new(0, 0, 2, 2), 1).unwrap();
+    assert_eq!((pattern.note(0, 0).unwrap().key, pattern.note(0, 0).unwrap().octave), (PianoKey::CSharp, 0));
+    assert_eq!((pattern.note(1, 0).unwrap().key, pattern.note(1, 0).unwrap().octave), (PianoKey::B, 9));
+
+    let block = pattern.copy(Selection::new(0, 6, 1, 2)).unwrap();
+    pattern.paste(3, 7, &block).unwrap();
+    assert_eq!(pattern.note(3, 7).unwrap().octave, 6);
+
+    pattern.clear(Selection::new(0, 0, 4, 8)).unwrap();
+    assert!(!pattern.note(3, 7).unwrap().initialized);
+
+    pattern.resize(2, 4).unwrap();
+    assert_eq!((pattern.channels, pattern.rows), (2, 4));
+    assert!(pattern.note(2, 0).is_none());
+}
+
+#[test]
+fn test_pattern_bounds() {
+    let mut pattern = create_pattern();
+
+    assert!(matches!(pattern.try_set_note(4, 0, Note::default()), Err(Error::OutOfBounds { channel: 4, row: 0 })));
+    assert!(matches!(pattern.copy(Selection::new(2, 4, 4, 2)), Err(Error::OutOfBounds { .. })));
+    assert!(matches!(pattern.delete_rows(0, 8), Err(Error::OutOfRange { .. })));
+    assert!(matches!(pattern.insert_rows(9, 1), Err(Error::OutOfBounds { .. })));
+    assert!(matches!(pattern.resize(0, 8), Err(Error::OutOfRange { .. })));
+
+    // Failed edits don't change anything.
+    assert_eq!((pattern.channels, pattern.rows), (4, 8));
+}
+
+#[test]
+fn test_undo_redo() {
+    let mut track = TrackBuilder::new().pattern(create_pattern()).pattern(Pattern::new(4, 16)).orders(&[0, 1]).build().unwrap();
+    let length = track.length_in_seconds;
+
+    let mut history = History::new();
+    history.apply(&mut track, Edit::DeleteRows { pattern: 0, row: 0, count: 4 }).unwrap();
+    history.apply(&mut track, Edit::InsertOrder { index: 2, pattern: 0 }).unwrap();
+    assert_eq!(track.orders, vec![0, 1, 0]);
+    assert_eq!(track.patterns[0].rows, 4);
+    // 8 + 16 rows before, 4 + 16 + 4 rows now.
+    assert!((track.length_in_seconds - length).abs() < 0.0001);
+    assert_eq!(track.seek_table.len(), 3);
+
+    assert!(matches!(history.apply(&mut track, Edit::SetOrder { index: 0, pattern: 2 }), Err(Error::MissingPattern { order: 0, pattern: 2 })));
+    assert!(matches!(history.apply(&mut track, Edit::RemoveOrder { index: 3 }), Err(Error::OrderOutOfBounds(3))));
+
+    assert!(history.undo(&mut track));
+    assert_eq!(track.orders, vec![0, 1]);
+    assert!(history.undo(&mut track));
+    assert_eq!(track.patterns[0].rows, 8);
+    assert_eq!(track.length_in_seconds, length);
+    assert!(!history.undo(&mut track));
+
+    assert!(history.redo(&mut track).unwrap());
+    assert_eq!(track.patterns[0].rows, 4);
+
+    // A new edit throws away anything that could be redone.
+    history.apply(&mut track, Edit::Transpose { pattern: 0, selection: Selection::new(0, 0, 1, 4), semitones: -12 }).unwrap();
+    assert!(!history.can_redo());
+    assert_eq!(track.patterns[0].note(0, 0).unwrap().octave, 3);
+}
+
+#[test]
+fn test_error_display() {
+    // The end of this selection is at the very last channel, which shouldn't overflow when shown 1-based.
+    let err = Pattern::new(2, 4).clear(Selection::new(1, 0, u16::MAX, 1)).unwrap_err();
+    assert_eq!(err, Error::OutOfBounds { channel: u16::MAX, row: 0 });
+    assert_eq!(err.to_string(), "channel 65536, row 0 is outside of the pattern");
+
+    let err = Error::MissingSample { pattern: 0, row: 0, channel: u16::MAX, sample: 1 };
+    assert_eq!(err.to_string(), "pattern 0, row 0, channel 65536 refers to sample 1, which doesn't exist");
+}
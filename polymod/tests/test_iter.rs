@@ -0,0 +1,47 @@
+use polymod::{builder::TrackBuilder, track::Pattern, Note, PianoKey, Effect, Arr2D};
+
+fn create_pattern(channels: u16, rows: u16, octave: u8) -> Pattern {
+    let mut pattern = Pattern::new(channels, rows);
+    for row in 0..rows {
+        pattern.set_note(0, row, Note::new(PianoKey::C, octave, None, None, Effect::None));
+    }
+
+    pattern
+}
+
+#[test]
+fn test_pattern_iterators() {
+    let mut arr = Arr2D::<u8>::new(3, 2);
+    arr.set(1, 0, 1);
+    arr.set(2, 1, 2);
+    assert_eq!(arr.iter_rows().collect::<Vec<_>>(), vec![&[0, 1, 0][..], &[0, 0, 2][..]]);
+    assert_eq!(arr.iter_column(2).copied().collect::<Vec<_>>(), vec![0, 2]);
+    assert_eq!(arr.iter_column(3).count(), 0);
+
+    let pattern = create_pattern(4, 8, 5);
+    assert_eq!(pattern.iter_rows().count(), 8);
+    assert!(pattern.iter_rows().all(|row| row.len() == 4 && row[0].key == PianoKey::C));
+
+    assert_eq!(pattern.iter_channel(0).filter(|note| note.initialized).count(), 8);
+    assert_eq!(pattern.iter_channel(1).filter(|note| note.initialized).count(), 0);
+    assert_eq!(pattern.iter_channel(4).count(), 0);
+}
+
+#[test]
+fn test_song_iterator() {
+    let track = TrackBuilder::new()
+        .pattern(create_pattern(2, 4, 1))
+        .pattern(create_pattern(3, 2, 2))
+        .orders(&[1, 254, 0, 255, 1])
+        .build()
+        .unwrap();
+
+    let notes: Vec<_> = track.iter_song().collect();
+
+    // Order 1 is skipped, and order 4 is after the end of the song.
+    assert_eq!(notes.len(), 3 * 2 + 2 * 4);
+    assert_eq!((notes[0].0, notes[0].1, notes[0].2, notes[0].3.octave), (0, 0, 0, 2));
+    assert_eq!((notes[5].0, notes[5].1, notes[5].2), (0, 1, 2));
+    assert_eq!((notes[6].0, notes[6].1, notes[6].2, notes[6].3.octave), (2, 0, 0, 1));
+    assert_eq!((notes[13].0, notes[13].1, notes[13].2), (2, 3, 1));
+}
@@ -0,0 +1,89 @@
+use polymod::{builder::TrackBuilder, track::{Pattern, Track}, Note, PianoKey, Effect};
+
+// At the default speed 6 and tempo 125, each row lasts 0.12 seconds and each tick 0.02.
+const ROW: f64 = 0.12;
+const TICK: f64 = 0.02;
+
+fn effect(pattern: &mut Pattern, channel: u16, row: u16, effect: Effect) {
+    pattern.set_note(channel, row, Note::new(PianoKey::None, 0, None, None, effect));
+}
+
+fn build(patterns: Vec<Pattern>, orders: &[u8]) -> Track {
+    patterns.into_iter().fold(TrackBuilder::new(), |builder, pattern| builder.pattern(pattern)).orders(orders).build().unwrap()
+}
+
+fn assert_length(track: &Track, seconds: f64) {
+    assert!((track.length_in_seconds - seconds).abs() < 0.0001, "expected {seconds}, got {}", track.length_in_seconds);
+}
+
+#[test]
+fn test_no_loop() {
+    let track = build(vec![Pattern::new(1, 8), Pattern::new(1, 4)], &[0, 254, 1, 255, 0]);
+
+    assert_length(&track, 12.0 * ROW);
+    assert_eq!(track.loop_start, None);
+    assert_eq!(track.seek_table.len(), 2);
+}
+
+#[test]
+fn test_position_jump() {
+    let mut pattern = Pattern::new(1, 4);
+    effect(&mut pattern, 0, 3, Effect::PositionJump(1));
+
+    let track = build(vec![Pattern::new(1, 8), pattern], &[0, 1]);
+
+    // The jump goes back to the start of the second order, which is where the song loops from.
+    assert_length(&track, 12.0 * ROW);
+    assert_eq!(track.loop_start, Some((1, 0)));
+}
+
+#[test]
+fn test_pattern_break() {
+    let mut pattern = Pattern::new(1, 8);
+    effect(&mut pattern, 0, 1, Effect::PatternBreak(6));
+
+    let track = build(vec![pattern, Pattern::new(1, 8)], &[0, 1]);
+
+    // 2 rows of the first order, then the second starts at row 6.
+    assert_length(&track, 4.0 * ROW);
+    assert_eq!(track.seek_table[1].rows[0].row, 6);
+}
+
+#[test]
+fn test_pattern_loop() {
+    let mut pattern = Pattern::new(2, 4);
+    effect(&mut pattern, 0, 1, Effect::Special(0xB0));
+    effect(&mut pattern, 0, 2, Effect::Special(0xB2));
+
+    let track = build(vec![pattern], &[0]);
+
+    // Rows 1 and 2 are played 3 times.
+    assert_length(&track, 8.0 * ROW);
+    assert_eq!(track.loop_start, None);
+}
+
+#[test]
+fn test_row_delays() {
+    let mut pattern = Pattern::new(2, 4);
+    effect(&mut pattern, 0, 0, Effect::Special(0xE2));
+    effect(&mut pattern, 1, 0, Effect::Special(0xE5));
+    effect(&mut pattern, 0, 1, Effect::Special(0x63));
+
+    let track = build(vec![pattern], &[0]);
+
+    // SE2 plays row 0 three times (the second SEx is ignored), and S63 adds 3 ticks to row 1.
+    assert_length(&track, 6.0 * ROW + 3.0 * TICK);
+}
+
+#[test]
+fn test_jump_over_pattern_loop() {
+    // Row 1 jumps to row 2, which loops back to the start. This used to loop forever.
+    let mut pattern = Pattern::new(2, 3);
+    effect(&mut pattern, 0, 1, Effect::PatternBreak(2));
+    effect(&mut pattern, 1, 1, Effect::PositionJump(0));
+    effect(&mut pattern, 0, 2, Effect::Special(0xB1));
+
+    let track = build(vec![pattern], &[0]);
+
+    assert_length(&track, 6.0 * ROW);
+}
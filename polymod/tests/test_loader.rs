@@ -0,0 +1,122 @@
+use polymod::{track::Track, track_player::{TrackPlayer, PlaybackState}, Error};
+
+/// Build a small IT file with one order, one empty sample slot and one pattern containing the given packed data.
+fn create_it(flags: u16, pattern: &[u8]) -> Vec<u8> {
+    let mut data = Vec::new();
+    data.extend_from_slice(b"IMPM");
+    data.extend_from_slice(&[0; 26]); // title
+    data.extend_from_slice(&[0; 2]); // highlight
+
+    data.extend_from_slice(&1u16.to_le_bytes()); // orders
+    data.extend_from_slice(&0u16.to_le_bytes()); // instruments
+    data.extend_from_slice(&0u16.to_le_bytes()); // samples
+    data.extend_from_slice(&1u16.to_le_bytes()); // patterns
+
+    data.extend_from_slice(&[0; 4]); // tracker version
+    data.extend_from_slice(&flags.to_le_bytes());
+    data.extend_from_slice(&0u16.to_le_bytes()); // special
+
+    data.extend_from_slice(&[128, 48, 6, 125]);
+    data.extend_from_slice(&[0; 12]);
+    data.extend_from_slice(&[32; 64]); // pans
+    data.extend_from_slice(&[64; 64]); // volumes
+
+    data.push(0); // orders
+
+    let offset = data.len() as u32 + 4;
+    data.extend_from_slice(&offset.to_le_bytes());
+
+    data.extend_from_slice(&(pattern.len() as u16).to_le_bytes());
+    data.extend_from_slice(&1u16.to_le_bytes()); // rows
+    data.extend_from_slice(&[0; 4]);
+    data.extend_from_slice(pattern);
+
+    data
+}
+
+#[test]
+fn test_load_errors() {
+    assert_eq!(Track::from_it(b"IMPS").err(), Some(Error::BadMagic { expected: "IMPM" }));
+    assert_eq!(Track::from_it(b"IMP").err(), Some(Error::TruncatedFile));
+
+    // Instrument mode.
+    assert_eq!(Track::from_it(&create_it(4, &[0])).err(), Some(Error::Unsupported("instruments")));
+
+    // The pattern data ends before the end of the row.
+    assert_eq!(Track::from_it(&create_it(0, &[0x81, 0x01])).err(), Some(Error::TruncatedFile));
+
+    // The pattern points past the end of the file.
+    let mut data = create_it(0, &[0]);
+    // The pattern offset comes straight after the header and the single order.
+    data[0xC1..0xC5].copy_from_slice(&0xFFFFu32.to_le_bytes());
+    assert_eq!(Track::from_it(&data).err(), Some(Error::InvalidOffset(0xFFFF)));
+}
+
+#[test]
+fn test_load_empty_instrument() {
+    // Channel 1, note C-5 with instrument 0, which means no instrument rather than underflowing.
+    let track = Track::from_it(&create_it(0, &[0x81, 0x03, 60, 0, 0])).unwrap();
+    let note = track.patterns[0].notes.get(0, 0);
+
+    assert_eq!(note.octave, 5);
+    assert_eq!(note.sample, None);
+}
+
+#[test]
+fn test_metadata() {
+    let mut data = create_it(0, &[0]);
+    data[0x04..0x0B].copy_from_slice(b"Example");
+    data[0x1E..0x20].copy_from_slice(&[4, 16]);
+    data[0x28..0x2A].copy_from_slice(&0x0214u16.to_le_bytes());
+
+    // Song message.
+    let message = b"Hello\rWorld\0";
+    data[0x2E] = 1;
+    data[0x36..0x38].copy_from_slice(&(message.len() as u16).to_le_bytes());
+    let offset = data.len() as u32;
+    data[0x38..0x3C].copy_from_slice(&offset.to_le_bytes());
+    data.extend_from_slice(message);
+
+    let metadata = Track::from_it(&data).unwrap().metadata;
+    assert_eq!(metadata.title, "Example");
+    assert_eq!(metadata.artist, None);
+    assert_eq!(metadata.message, "Hello\nWorld");
+    assert_eq!(metadata.tracker, "Impulse Tracker 2.14");
+    assert_eq!(metadata.highlight, (4, 16));
+    assert!(metadata.samples.is_empty());
+}
+
+#[test]
+fn test_fuzz_corpus() {
+    // The seed corpus should all load and play, otherwise the fuzzer won't get very far with it.
+    let corpus = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("../fuzz/corpus/load_track");
+
+    for entry in std::fs::read_dir(corpus).unwrap() {
+        let path = entry.unwrap().path();
+        let track = Track::from_it(&std::fs::read(&path).unwrap()).unwrap_or_else(|err| panic!("{}: {err}", path.display()));
+
+        let mut player = TrackPlayer::new(&track, 8000);
+        player.set_max_loops(Some(0));
+
+        let mut buffer = vec![0.0; 1 << 16];
+        while player.state() != PlaybackState::Finished {
+            player.render(&mut buffer);
+        }
+    }
+}
+
+#[test]
+fn test_low_sample_rate() {
+    // Rates this low break the filter maths, so the loader falls back to middle C.
+    let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("../fuzz/corpus/load_track/low_rate_filter.it");
+    let track = Track::from_it(&std::fs::read(path).unwrap()).unwrap();
+    assert_eq!(track.samples[0].format.sample_rate, 8363);
+}
+
+#[test]
+fn test_pattern_width() {
+    // Only channel 3 has anything in it, so patterns don't need to be any wider than that.
+    let track = Track::from_it(&create_it(0, &[0x83, 0x01, 60, 0])).unwrap();
+    assert_eq!((track.patterns[0].channels, track.patterns[0].rows), (3, 1));
+    assert_eq!(track.num_channels(), 3);
+}
@@ -0,0 +1,29 @@
+use polymod::midi::{self, MidiMacros, MacroCommand, MIDI_CONFIG_LENGTH};
+
+#[test]
+fn test_default_macros() {
+    let macros = MidiMacros::it_default();
+
+    assert_eq!(macros.zxx_macro(0, 0x40), "F0F000z");
+    assert_eq!(midi::evaluate_macro(macros.zxx_macro(0, 0x40), 0x40), vec![MacroCommand::FilterCutoff(0x40)]);
+    assert_eq!(midi::evaluate_macro(macros.zxx_macro(0, 0x83), 0x83), vec![MacroCommand::FilterResonance(24)]);
+    assert!(midi::evaluate_macro(macros.zxx_macro(1, 0x10), 0x10).is_empty());
+}
+
+#[test]
+fn test_embedded_macros() {
+    let mut data = vec![0; MIDI_CONFIG_LENGTH];
+
+    // SF1 = resonance, Z80 = cutoff of 0x20.
+    let sf1 = 32 * (9 + 1);
+    data[sf1..sf1 + 7].copy_from_slice(b"F0F001z");
+    let z80 = 32 * (9 + 16);
+    data[z80..z80 + 8].copy_from_slice(b"F0F00020");
+
+    let macros = MidiMacros::from_bytes(&data);
+
+    assert_eq!(macros.parametered.len(), 16);
+    assert_eq!(macros.fixed.len(), 128);
+    assert_eq!(midi::evaluate_macro(macros.zxx_macro(1, 0x7F), 0x7F), vec![MacroCommand::FilterResonance(0x7F)]);
+    assert_eq!(midi::evaluate_macro(macros.zxx_macro(0, 0x80), 0x80), vec![MacroCommand::FilterCutoff(0x20)]);
+}
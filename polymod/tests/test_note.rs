@@ -0,0 +1,30 @@
+use polymod::{Note, PianoKey, Effect, Error};
+
+#[test]
+fn test_piano_key_conversions() {
+    assert_eq!(PianoKey::from_semitone(0), Some(PianoKey::C));
+    assert_eq!(PianoKey::from_semitone(11), Some(PianoKey::B));
+    assert_eq!(PianoKey::from_semitone(12), None);
+    assert_eq!(PianoKey::NoteCut.semitone(), None);
+
+    for value in 0..16 {
+        let key = PianoKey::try_from(value).unwrap();
+        assert_eq!(key as u8, value);
+    }
+
+    assert_eq!(PianoKey::try_from(16), Err(Error::InvalidNote(16)));
+}
+
+#[test]
+fn test_midi_conversions() {
+    let note = Note::from_midi(60, Some(1), None, Effect::None).unwrap();
+    assert_eq!((note.key, note.octave, note.sample), (PianoKey::C, 5, Some(1)));
+
+    for number in 0..128 {
+        assert_eq!(Note::from_midi(number, None, None, Effect::None).unwrap().to_midi(), Some(number));
+    }
+
+    assert!(Note::from_midi(128, None, None, Effect::None).is_none());
+    assert_eq!(Note::new(PianoKey::NoteOff, 5, None, None, Effect::None).to_midi(), None);
+    assert_eq!(Note::new(PianoKey::A, 10, None, None, Effect::None).to_midi(), None);
+}
@@ -0,0 +1,472 @@
+use std::sync::Arc;
+
+use mixr::{AudioFormat, FormatType};
+use polymod::{builder::TrackBuilder, track::{Track, Pattern}, track_player::{TrackPlayer, PlaybackState, NoteTarget, LIVE_VOICES, calculate_half_samples_per_tick}, sample::Sample, event::{PlayerEvent, TimedEvent}, Note, PianoKey, Effect};
+
+fn create_track(orders: Vec<u8>) -> Track {
+    let mut first = Pattern::new(4, 8);
+    first.set_note(0, 0, Note::new(PianoKey::None, 0, None, None, Effect::SetSpeed(2)));
+
+    let mut second = Pattern::new(4, 8);
+    second.set_note(1, 4, Note::new(PianoKey::None, 0, None, None, Effect::PatternBreak(2)));
+
+    // Some tests need orders the builder won't accept, so they are set afterwards.
+    let mut track = TrackBuilder::new().pattern(first).pattern(second).build().unwrap();
+    track.orders = orders;
+    track.update_length();
+
+    track
+}
+
+fn create_sample(sample_rate: i32, length: usize) -> Sample {
+    let format = AudioFormat { format_type: FormatType::I8, channels: 1, sample_rate, ..AudioFormat::default() };
+    Sample::new(&vec![64; length], format, true, 0, length as i32, 64, 64)
+}
+
+#[test]
+fn test_seek_position() {
+    let track = create_track(vec![0, 1, 0, 255]);
+    let mut player = TrackPlayer::new(&track, 48000);
+
+    assert!(player.seek_position(2, 3));
+    assert_eq!(player.position(), (2, 3, 0));
+
+    // Order 0 is 8 rows at speed 2, order 1 breaks after 5 rows, and the pattern break skips rows 0 and 1 of order 2.
+    let ticks = 8 * 2 + 5 * 2 + 2;
+    let expected = ticks as f64 * 2.5 / 125.0;
+    assert!((player.elapsed_seconds() - expected).abs() < 0.001);
+
+    assert!(!player.seek_position(3, 0));
+    assert!(!player.seek_position(0, 8));
+}
+
+#[test]
+fn test_loop_count() {
+    let track = create_track(vec![0, 255]);
+    let mut player = TrackPlayer::new(&track, 48000);
+    player.set_max_loops(Some(1));
+
+    // One play through is 8 rows at speed 2.
+    let frames_per_loop = (8 * 2) * (2.5 / 125.0 * 48000.0) as usize;
+
+    let mut buffer = vec![0.0; frames_per_loop * 2];
+    assert_eq!(player.render(&mut buffer), frames_per_loop);
+    assert_eq!(player.state(), PlaybackState::Looped(1));
+    assert!(player.take_looped());
+    assert!(!player.take_looped());
+
+    assert_eq!(player.render(&mut buffer), frames_per_loop);
+    assert_eq!(player.state(), PlaybackState::Finished);
+    assert_eq!(player.render(&mut buffer), 0);
+}
+
+#[test]
+fn test_mute_solo() {
+    let track = create_track(vec![0, 1]);
+    let mut player = TrackPlayer::new(&track, 48000);
+
+    player.set_channel_muted(1, true);
+    let states = player.channel_states();
+    assert_eq!(states.len(), 4);
+    assert!(states[0].audible);
+    assert!(!states[1].audible);
+
+    player.set_channel_solo(2, true);
+    let states = player.channel_states();
+    assert!(!states[0].audible);
+    assert!(states[2].audible && states[2].solo);
+
+    // Mute and solo should survive seeking.
+    player.seek_seconds(0.5);
+    assert_eq!(player.channel_states(), states);
+}
+
+#[test]
+fn test_stems() {
+    // The same sample is played on the first two channels.
+    let mut pattern = Pattern::new(4, 8);
+    pattern.set_note(0, 0, Note::new(PianoKey::C, 5, Some(0), Some(64), Effect::None));
+    pattern.set_note(1, 0, Note::new(PianoKey::C, 5, Some(0), Some(64), Effect::None));
+
+    let track = TrackBuilder::new().pattern(pattern).orders(&[0, 255]).sample(create_sample(8363, 256)).build().unwrap();
+    let mut player = TrackPlayer::new(&track, 48000);
+    assert_eq!(player.num_stems(), 0);
+
+    player.set_stems_enabled(true);
+    assert_eq!(player.num_stems(), 4);
+
+    player.set_channel_muted(1, true);
+
+    let mut master = vec![0.0; 1024];
+    let mut stems = vec![vec![1.0; 1024]; player.num_stems()];
+    assert_eq!(player.render_stems(&mut master, &mut stems), 512);
+
+    let silent = |buffer: &[f32]| buffer.iter().all(|value| *value == 0.0);
+    assert!(!silent(&master));
+    assert!(!silent(&stems[0]));
+
+    // The muted channel and the ones with nothing playing on them shouldn't be heard in their stems.
+    assert!(silent(&stems[1]));
+    assert!(silent(&stems[2]) && silent(&stems[3]));
+}
+
+#[test]
+fn test_events() {
+    let track = create_track(vec![0, 1]);
+    let mut player = TrackPlayer::new(&track, 48000);
+    player.set_events_enabled(true);
+
+    // Render the first two rows, at speed 2 with 960 frames per tick.
+    let mut buffer = vec![0.0; 960 * 4 * 2];
+    player.render(&mut buffer);
+
+    let events = player.drain_events().collect::<Vec<_>>();
+    assert_eq!(events.len(), 4);
+    assert_eq!(events[0].event, PlayerEvent::OrderChanged { order: 0, pattern: 0 });
+    assert_eq!(events[1].event, PlayerEvent::RowChanged { order: 0, row: 0 });
+    assert_eq!(events[2].event, PlayerEvent::EffectExecuted { channel: 0, effect: Effect::SetSpeed(2) });
+    assert_eq!(events[3], TimedEvent { frame: 960 * 2, event: PlayerEvent::RowChanged { order: 0, row: 1 } });
+
+    assert_eq!(player.drain_events().count(), 0);
+
+    player.seek_position(1, 0);
+    player.render(&mut buffer);
+    let events = player.drain_events().collect::<Vec<_>>();
+    assert_eq!(events[0].event, PlayerEvent::OrderChanged { order: 1, pattern: 1 });
+}
+
+#[test]
+fn test_shared_player() {
+    let track = Arc::new(create_track(vec![0, 1]));
+    let mut player = TrackPlayer::new(track.clone(), 48000);
+
+    let thread = std::thread::spawn(move || {
+        let mut buffer = vec![0.0; 64];
+        player.render(&mut buffer)
+    });
+
+    assert_eq!(thread.join().unwrap(), 32);
+    assert_eq!(Arc::strong_count(&track), 1);
+}
+
+#[test]
+fn test_handle() {
+    let track = create_track(vec![0, 1]);
+    let mut player = TrackPlayer::new(&track, 48000);
+    let handle = player.handle();
+
+    handle.seek_position(1, 2);
+    handle.set_paused(true);
+
+    // Nothing happens until the player next reaches a tick boundary.
+    assert_eq!(player.position(), (0, 0, 0));
+
+    let mut buffer = vec![1.0; 64];
+    assert_eq!(player.render(&mut buffer), 32);
+    assert!(player.is_paused());
+    assert_eq!(player.position(), (1, 2, 0));
+    assert!(buffer.iter().all(|value| *value == 0.0));
+
+    handle.stop();
+    assert_eq!(player.render(&mut buffer), 0);
+    assert_eq!(player.state(), PlaybackState::Finished);
+}
+
+#[test]
+fn test_pause_mid_tick() {
+    let track = create_track(vec![0, 1]);
+    let mut player = TrackPlayer::new(&track, 48000);
+    let handle = player.handle();
+
+    let mut buffer = vec![0.0; 200];
+    assert_eq!(player.render(&mut buffer), 100);
+    let elapsed = player.elapsed_seconds();
+
+    // Pausing part way through a tick still needs the handle to be able to resume it.
+    player.set_paused(true);
+    assert_eq!(player.render(&mut buffer), 100);
+    assert_eq!(player.elapsed_seconds(), elapsed);
+
+    handle.set_paused(false);
+    assert_eq!(player.render(&mut buffer), 100);
+    assert!(!player.is_paused());
+    assert!(player.elapsed_seconds() > elapsed);
+
+    // The same goes for anything else sent while paused.
+    player.set_paused(true);
+    handle.seek_position(1, 2);
+    handle.set_paused(false);
+    player.render(&mut buffer[..2]);
+    assert_eq!(player.position(), (1, 2, 0));
+}
+
+#[test]
+fn test_live_notes() {
+    let mut track = create_track(vec![0, 1]);
+    track.samples.push(create_sample(8363, 256));
+
+    let mut player = TrackPlayer::new(&track, 48000);
+    player.set_events_enabled(true);
+    assert_eq!(player.num_free_voices(), LIVE_VOICES);
+
+    // Free voices start after the track's own channels, and aren't reported as channels.
+    assert_eq!(player.play_note(NoteTarget::FreeVoice, 0, PianoKey::C, 5, 64), Some(4));
+    assert_eq!(player.play_note(NoteTarget::FreeVoice, 0, PianoKey::E, 5, 64), Some(5));
+    assert_eq!(player.channel_states().len(), 4);
+
+    player.stop_note(4);
+    assert_eq!(player.play_note(NoteTarget::FreeVoice, 0, PianoKey::G, 5, 64), Some(4));
+
+    assert_eq!(player.play_note(NoteTarget::Channel(2), 0, PianoKey::C, 4, 32), Some(2));
+    assert_eq!(player.play_note(NoteTarget::Channel(100), 0, PianoKey::C, 4, 32), None);
+    assert_eq!(player.play_note(NoteTarget::FreeVoice, 1, PianoKey::C, 5, 64), None);
+    for key in [PianoKey::None, PianoKey::NoteCut, PianoKey::NoteOff, PianoKey::NoteFade] {
+        assert_eq!(player.play_note(NoteTarget::FreeVoice, 0, key, 5, 64), None);
+    }
+
+    let events = player.drain_events().map(|event| event.event).collect::<Vec<_>>();
+    assert_eq!(events.len(), 4);
+    assert_eq!(events[3], PlayerEvent::NoteTriggered { channel: 2, sample: 0, key: PianoKey::C, octave: 4, volume: 32 });
+}
+
+#[test]
+fn test_invalid_orders() {
+    // Markers and missing patterns are skipped over.
+    let track = create_track(vec![254, 7, 1, 255, 0]);
+    let player = TrackPlayer::new(&track, 48000);
+    assert_eq!(player.position(), (2, 0, 0));
+
+    let track = create_track(vec![255, 0]);
+    let mut player = TrackPlayer::new(&track, 48000);
+    let mut buffer = vec![0.0; 64];
+    assert_eq!(player.state(), PlaybackState::Finished);
+    assert_eq!(player.render(&mut buffer), 0);
+
+    // The pattern after the end marker can still be played directly, but there's nowhere to loop back to.
+    assert!(player.seek_position(1, 0));
+    assert_eq!(player.state(), PlaybackState::Playing);
+    player.set_tempo_tuning(f64::INFINITY);
+    player.render(&mut buffer);
+    assert_eq!(player.state(), PlaybackState::Finished);
+
+    let track = create_track(Vec::new());
+    let player = TrackPlayer::new(&track, 48000);
+    assert_eq!(player.state(), PlaybackState::Finished);
+}
+
+#[test]
+fn test_zero_tempo_and_speed() {
+    let mut track = create_track(vec![0, 1]);
+    track.tempo = 0;
+    track.speed = 0;
+
+    let mut player = TrackPlayer::new(&track, 48000);
+    player.set_max_loops(Some(0));
+    player.set_tempo_tuning(f64::INFINITY);
+
+    // Every tick is as short as it can be, so this will get through the whole song.
+    let mut buffer = vec![0.0; 8192];
+    player.render(&mut buffer);
+    assert_eq!(player.state(), PlaybackState::Finished);
+}
+
+#[test]
+fn test_filters() {
+    let mut track = create_track(vec![0, 1]);
+    track.samples.push(create_sample(2, 256));
+    track.patterns[0].set_note(2, 1, Note::new(PianoKey::C, 5, Some(0), None, Effect::MidiMacro(0x10)));
+    track.patterns[0].set_note(2, 3, Note::new(PianoKey::None, 0, None, None, Effect::MidiMacro(0x40)));
+
+    // Filters are set up when the player is created, and playing through them shouldn't panic even at tiny rates.
+    let mut player = TrackPlayer::new(&track, 48000);
+    player.set_max_loops(Some(0));
+
+    let mut buffer = vec![0.0; 8192];
+    while player.state() != PlaybackState::Finished {
+        player.render(&mut buffer);
+    }
+}
+
+#[test]
+fn test_seek_effect_memory() {
+    let mut track = create_track(vec![0, 1]);
+    track.samples.push(create_sample(8363, 2048));
+
+    let pattern = &mut track.patterns[0];
+    pattern.set_note(1, 0, Note::new(PianoKey::C, 5, Some(0), Some(48), Effect::SampleOffset(0x02)));
+    pattern.set_note(1, 1, Note::new(PianoKey::None, 0, None, None, Effect::VolumeSlide(0x04)));
+    pattern.set_note(1, 3, Note::new(PianoKey::C, 5, Some(0), Some(40), Effect::SampleOffset(0x00)));
+    pattern.set_note(1, 4, Note::new(PianoKey::None, 0, None, None, Effect::VolumeSlide(0x00)));
+
+    // Land just after the second tick of row 4, at speed 2 with 0.02 seconds per tick.
+    let mut seeked = TrackPlayer::new(&track, 48000);
+    let seconds = seeked.seek_seconds(0.201);
+    assert!((seconds - 0.2).abs() < 0.0001);
+    assert_eq!(seeked.position(), (0, 5, 0));
+
+    let mut played = TrackPlayer::new(&track, 48000);
+    let mut buffer = vec![0.0; (seconds * 48000.0).round() as usize * 2];
+    played.render(&mut buffer);
+
+    assert_eq!(played.position(), seeked.position());
+    assert_eq!(played.channel_states(), seeked.channel_states());
+
+    // Both the volume slide and the sample offset came from memory.
+    let state = seeked.channel_states()[1];
+    assert_eq!((state.sample, state.volume), (Some(0), 36));
+    assert!(state.position > 512.0 && state.position < 2048.0, "{}", state.position);
+}
+
+#[test]
+fn test_seek_past_end() {
+    let track = create_track(vec![0, 1]);
+
+    // The song loops forever, so there's nothing past the end to seek to.
+    let mut player = TrackPlayer::new(&track, 48000);
+    let seconds = player.seek_seconds(f64::INFINITY);
+    assert!((seconds - (8.0 * 2.0 + 5.0 * 2.0) * 0.02).abs() < 0.0001, "{seconds}");
+    assert_eq!(player.position(), (0, 0, 0));
+
+    player.set_max_loops(Some(0));
+    player.seek_seconds(1000.0);
+    assert_eq!(player.state(), PlaybackState::Finished);
+}
+
+#[test]
+fn test_sample_rates() {
+    let track = create_track(vec![0, 255]);
+
+    for sample_rate in [44100, 48000, 96000] {
+        let player = TrackPlayer::new(&track, sample_rate);
+        assert_eq!(player.sample_rate(), sample_rate);
+
+        // A tick at tempo 125 lasts 1/50th of a second whatever the rate, with two half samples per frame.
+        assert_eq!(calculate_half_samples_per_tick(125, sample_rate), sample_rate as u32 / 50 * 2);
+    }
+}
+
+#[test]
+fn test_voices() {
+    let track = create_track(vec![0, 1]);
+    assert_eq!(track.num_channels(), 4);
+
+    // One voice per channel, plus the live ones.
+    let player = TrackPlayer::new(&track, 48000);
+    assert_eq!(player.channel_states().len(), 4);
+    assert_eq!(player.num_free_voices(), LIVE_VOICES);
+
+    // Channels past the voice count aren't played, and there's nothing left for live notes.
+    let player = TrackPlayer::with_voices(&track, 48000, 2);
+    assert_eq!(player.channel_states().len(), 2);
+    assert_eq!(player.num_free_voices(), 0);
+
+    // Voices past the track's pans are just centered.
+    let mut player = TrackPlayer::with_voices(&track, 48000, 80);
+    for _ in 0..48000 {
+        player.advance();
+    }
+}
+
+#[test]
+fn test_render_end() {
+    let track = create_track(vec![0, 255]);
+    let frames_per_song = (8 * 2) * (2.5 / 125.0 * 48000.0) as usize;
+
+    // Room for twice the song, but only the song itself should be rendered.
+    let mut player = TrackPlayer::new(&track, 48000);
+    player.set_max_loops(Some(0));
+    let mut buffer = vec![0.0; frames_per_song * 4];
+    assert_eq!(player.render(&mut buffer), frames_per_song);
+    assert_eq!(player.state(), PlaybackState::Finished);
+    assert_eq!(player.render(&mut buffer), 0);
+
+    let mut player = TrackPlayer::new(&track, 48000);
+    player.set_max_loops(Some(0));
+    let mut buffer = vec![0; frames_per_song * 4];
+    assert_eq!(player.render_i16(&mut buffer), frames_per_song);
+    assert_eq!(player.state(), PlaybackState::Finished);
+}
+
+/// Play a single pattern track to the end, and check it took as long as its calculated length.
+fn assert_plays_for_length(pattern: Pattern) {
+    let track = TrackBuilder::new().pattern(pattern).orders(&[0]).build().unwrap();
+    let mut player = TrackPlayer::new(&track, 48000);
+    player.set_max_loops(Some(0));
+
+    let mut frames = 0;
+    let mut buffer = vec![0.0; 4096];
+    while player.state() != PlaybackState::Finished {
+        frames += player.render(&mut buffer);
+    }
+
+    let seconds = frames as f64 / 48000.0;
+    assert!((seconds - track.length_in_seconds).abs() < 0.001, "played {seconds}, expected {}", track.length_in_seconds);
+}
+
+fn set_effect(pattern: &mut Pattern, channel: u16, row: u16, effect: Effect) {
+    pattern.set_note(channel, row, Note::new(PianoKey::None, 0, None, None, effect));
+}
+
+#[test]
+fn test_length_matches_playback() {
+    // Pattern loops, row delays and tempo slides all change how long the song takes to play.
+    let mut pattern = Pattern::new(2, 8);
+    set_effect(&mut pattern, 0, 1, Effect::Special(0xB0));
+    set_effect(&mut pattern, 0, 2, Effect::Special(0xB2));
+    set_effect(&mut pattern, 1, 3, Effect::Special(0xE2));
+    set_effect(&mut pattern, 0, 4, Effect::Special(0x63));
+    set_effect(&mut pattern, 1, 5, Effect::Tempo(0x18));
+    set_effect(&mut pattern, 1, 6, Effect::Tempo(0x05));
+    set_effect(&mut pattern, 0, 7, Effect::Tempo(0x20));
+    assert_plays_for_length(pattern);
+
+    // A break and a jump back over a pattern loop, which comes round once more after the jump.
+    let mut pattern = Pattern::new(2, 3);
+    set_effect(&mut pattern, 0, 1, Effect::PatternBreak(2));
+    set_effect(&mut pattern, 1, 1, Effect::PositionJump(0));
+    set_effect(&mut pattern, 0, 2, Effect::Special(0xB1));
+    assert_plays_for_length(pattern);
+}
+
+#[test]
+fn test_position_jump_loop() {
+    let frames_per_row = 2 * (2.5 / 125.0 * 48000.0) as usize;
+
+    // Order 0 skips straight to order 1, which jumps back to the rest of order 0 and then on to order 2. Nothing is
+    // played twice, so the song doesn't loop until it runs out of orders.
+    let mut first = Pattern::new(2, 8);
+    set_effect(&mut first, 0, 0, Effect::SetSpeed(2));
+    set_effect(&mut first, 1, 0, Effect::PositionJump(1));
+    set_effect(&mut first, 1, 7, Effect::PositionJump(2));
+
+    let mut second = Pattern::new(2, 8);
+    set_effect(&mut second, 0, 3, Effect::PatternBreak(4));
+    set_effect(&mut second, 1, 3, Effect::PositionJump(0));
+
+    let track = TrackBuilder::new().pattern(first).pattern(second).pattern(Pattern::new(2, 8)).orders(&[0, 1, 2]).build().unwrap();
+    assert_eq!(track.loop_start, None);
+
+    let mut player = TrackPlayer::new(&track, 48000);
+    player.set_max_loops(Some(0));
+
+    let frames = (1 + 4 + 4 + 8) * frames_per_row;
+    let mut buffer = vec![0.0; frames * 4];
+    assert_eq!(player.render(&mut buffer), frames);
+    assert_eq!(player.state(), PlaybackState::Finished);
+
+    // Jumping back to a row that's already been played does loop, from that row.
+    let mut second = Pattern::new(2, 8);
+    set_effect(&mut second, 0, 3, Effect::PositionJump(1));
+
+    let track = TrackBuilder::new().pattern(create_track(vec![]).patterns.remove(0)).pattern(second).orders(&[0, 1]).build().unwrap();
+    assert_eq!(track.loop_start, Some((1, 0)));
+
+    let mut player = TrackPlayer::new(&track, 48000);
+    let frames = (8 + 4) * frames_per_row;
+    let mut buffer = vec![0.0; frames * 2];
+    assert_eq!(player.render(&mut buffer), frames);
+
+    assert_eq!(player.state(), PlaybackState::Looped(1));
+    assert!(player.take_looped());
+    assert_eq!(player.position(), (1, 0, 0));
+}
@@ -35,13 +35,13 @@ fn test_track_player() {
     pattern.set_note(3, 3, Note::new(polymod::PianoKey::C, 6, 0, 64, polymod::Effect::None, 0));
 
     let track = Track { patterns: vec![pattern], orders: vec![0], samples: vec![sample], tempo: 125, speed: 6, global_volume: 128 };
-    let mut player = TrackPlayer::new(&track);
+    let mut player = TrackPlayer::new(&track, polymod::track_player::DEFAULT_SAMPLE_RATE);
     
     let sdl = sdl2::init().unwrap();
     let audio = sdl.audio().unwrap();
 
     let desired_spec = AudioSpecDesired {
-        freq: Some(polymod::track_player::SAMPLE_RATE),
+        freq: Some(polymod::track_player::DEFAULT_SAMPLE_RATE),
         channels: Some(2),
         samples: Some(8192)
     };
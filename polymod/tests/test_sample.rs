@@ -0,0 +1,53 @@
+use mixr::{AudioFormat, FormatType};
+use polymod::{sample::{Sample, SamplePlayer}, PianoKey};
+
+fn create_sample(looping: bool) -> Sample {
+    let format = AudioFormat { format_type: FormatType::I8, channels: 1, sample_rate: 8363, ..AudioFormat::default() };
+
+    Sample::new(&[64; 256], format, looping, 0, 256, 64, 64)
+}
+
+#[test]
+fn test_sample_player() {
+    let sample = create_sample(false);
+    let mut player = SamplePlayer::new(&sample, 48000);
+
+    let mut buffer = vec![0.0; 8192];
+    assert_eq!(player.render(&mut buffer), 0);
+
+    // At C-5 the sample plays at its own sample rate, so 256 frames at 8363Hz should last ~1470 frames at 48000Hz.
+    player.play(PianoKey::C, 5, 64);
+    let frames = player.render(&mut buffer);
+    assert!((1469..=1471).contains(&frames), "{frames}");
+    assert!(!player.is_playing());
+
+    // An octave up should take half as long.
+    player.play(PianoKey::C, 6, 64);
+    let frames = player.render(&mut buffer);
+    assert!((734..=736).contains(&frames), "{frames}");
+}
+
+#[test]
+fn test_sample_player_looping() {
+    let sample = create_sample(true);
+    let mut player = SamplePlayer::new(&sample, 48000);
+
+    player.play(PianoKey::C, 5, 64);
+
+    let mut buffer = vec![0.0; 8192];
+    assert_eq!(player.render(&mut buffer), 4096);
+    assert!(player.is_playing());
+
+    player.stop();
+    assert_eq!(player.render(&mut buffer), 0);
+}
+
+#[test]
+fn test_filtered_low_sample_rate() {
+    // The filter's cutoff is limited by the sample rate, which used to panic for rates this low.
+    let mut sample = create_sample(false);
+    sample.format.sample_rate = 2;
+
+    let data = sample.filtered(1000.0, 64);
+    assert_eq!(data.len(), 256 * 2);
+}
@@ -21,13 +21,13 @@ impl<'a> AudioCallback for Audio<'a> {
 pub fn test_track() {
     let track = Track::from_it("/home/ollie/Music/Modules/Created/TestPlayback.it").unwrap();
 
-    let mut player = TrackPlayer::new(&track);
+    let mut player = TrackPlayer::new(&track, polymod::track_player::DEFAULT_SAMPLE_RATE);
     
     let sdl = sdl2::init().unwrap();
     let audio = sdl.audio().unwrap();
 
     let desired_spec = AudioSpecDesired {
-        freq: Some(polymod::track_player::SAMPLE_RATE),
+        freq: Some(polymod::track_player::DEFAULT_SAMPLE_RATE),
         channels: Some(2),
         samples: Some(8192)
     };
@@ -0,0 +1,127 @@
+use mixr::{AudioFormat, FormatType};
+use polymod::{builder::TrackBuilder, track::{Track, Pattern, TrackMetadata, SampleMetadata}, sample::Sample, midi::MidiMacros, Note, PianoKey, Effect, ModuleType, Error};
+
+fn create_track() -> Track {
+    let mut first = Pattern::new(4, 32);
+    first.set_note(0, 0, Note::new(PianoKey::C, 5, Some(0), Some(64), Effect::SetSpeed(4)));
+    first.set_note(1, 0, Note::new(PianoKey::CSharp, 3, Some(1), None, Effect::None));
+    first.set_note(0, 1, Note::new(PianoKey::C, 5, Some(0), Some(64), Effect::SetSpeed(4)));
+    first.set_note(0, 2, Note::new(PianoKey::B, 9, Some(0), Some(32), Effect::SampleOffset(2)));
+    first.set_note(2, 4, Note::new(PianoKey::NoteCut, 0, None, None, Effect::None));
+    first.set_note(3, 8, Note::new(PianoKey::NoteOff, 0, None, None, Effect::MidiMacro(0x40)));
+    first.set_note(3, 9, Note::new(PianoKey::NoteFade, 0, None, None, Effect::None));
+    first.set_note(1, 31, Note::new(PianoKey::None, 0, None, None, Effect::PatternBreak(0)));
+
+    let mut second = Pattern::new(4, 64);
+    second.set_note(0, 10, Note::new(PianoKey::G, 4, Some(1), Some(10), Effect::Special(0xB2)));
+    second.set_note(3, 63, Note::new(PianoKey::None, 0, None, None, Effect::None));
+
+    let format = AudioFormat { format_type: FormatType::I8, channels: 1, sample_rate: 8363, ..AudioFormat::default() };
+    let mono = Sample::new(&(0..100).map(|i| i as u8).collect::<Vec<_>>(), format, true, 10, 90, 48, 64);
+
+    let format = AudioFormat { format_type: FormatType::I16, channels: 2, sample_rate: 22050, ..AudioFormat::default() };
+    let stereo = Sample::new(&(0..200).map(|i| i as u8).collect::<Vec<_>>(), format, false, 0, -1, 64, 32);
+
+    let mut midi_macros = MidiMacros::it_default();
+    midi_macros.parametered[1] = String::from("F0F001z");
+
+    let mut builder = TrackBuilder::new()
+        .mod_type(ModuleType::IT)
+        .pattern(first)
+        .pattern(second)
+        .orders(&[0, 1, 0, 255])
+        .sample(mono)
+        .sample(stereo)
+        .tempo(140)
+        .speed(5)
+        .global_volume(100)
+        .mix_volume(80)
+        .midi_macros(midi_macros)
+        .metadata(TrackMetadata {
+            title: String::from("Round trip"),
+            artist: None,
+            message: String::from("Line one\nLine two"),
+            tracker: String::new(),
+            samples: vec![
+                SampleMetadata { name: String::from("Mono"), filename: String::from("mono.raw") },
+                SampleMetadata { name: String::from("Stereo"), filename: String::from("stereo.wav") }
+            ],
+            highlight: (4, 16)
+        });
+
+    // Set every channel IT stores, so the pans read back the same.
+    for channel in 0..64 {
+        builder = builder.pan(channel, if channel < 4 { channel as u8 * 16 } else { 160 });
+    }
+
+    builder.build().unwrap()
+}
+
+#[test]
+fn test_round_trip() {
+    let track = create_track();
+    let loaded = Track::from_it(&track.to_it().unwrap()).unwrap();
+
+    assert_eq!(loaded.orders, track.orders);
+    assert_eq!((loaded.tempo, loaded.speed, loaded.global_volume, loaded.mix_volume), (140, 5, 100, 80));
+    assert_eq!(loaded.pans, track.pans);
+    assert_eq!(loaded.midi_macros, track.midi_macros);
+
+    assert_eq!(loaded.patterns.len(), track.patterns.len());
+    for (loaded, pattern) in loaded.patterns.iter().zip(track.patterns.iter()) {
+        assert_eq!((loaded.channels, loaded.rows), (pattern.channels, pattern.rows));
+
+        for row in 0..pattern.rows as usize {
+            for channel in 0..pattern.channels as usize {
+                assert_eq!(loaded.notes.get(channel, row), pattern.notes.get(channel, row), "channel {channel}, row {row}");
+            }
+        }
+    }
+
+    assert_eq!(loaded.samples.len(), track.samples.len());
+    for (loaded, sample) in loaded.samples.iter().zip(track.samples.iter()) {
+        assert_eq!(loaded.data, sample.data);
+        assert_eq!(loaded.format.bytes_per_sample(), sample.format.bytes_per_sample());
+        assert_eq!((loaded.format.channels, loaded.format.sample_rate), (sample.format.channels, sample.format.sample_rate));
+        assert_eq!((loaded.looping, loaded.loop_start, loaded.loop_end), (sample.looping, sample.loop_start, sample.loop_end));
+        assert_eq!((loaded.global_volume, loaded.default_volume), (sample.global_volume, sample.default_volume));
+    }
+
+    let metadata = &loaded.metadata;
+    assert_eq!(metadata.title, "Round trip");
+    assert_eq!(metadata.message, "Line one\nLine two");
+    assert_eq!(metadata.tracker, "Impulse Tracker 2.14");
+    assert_eq!(metadata.highlight, (4, 16));
+    assert_eq!(metadata.samples[1].name, "Stereo");
+    assert_eq!(metadata.samples[1].filename, "stereo.wav");
+}
+
+#[test]
+fn test_missing_end_marker() {
+    let mut track = create_track();
+    track.orders = vec![1, 0];
+
+    let loaded = Track::from_it(&track.to_it().unwrap()).unwrap();
+    assert_eq!(loaded.orders, vec![1, 0, 255]);
+}
+
+#[test]
+fn test_too_large() {
+    let mut track = create_track();
+    track.patterns[1] = Pattern::new(4, 201);
+    assert!(matches!(track.to_it(), Err(Error::OutOfRange { value: 201, .. })));
+
+    track.patterns[1] = Pattern::new(65, 64);
+    assert!(matches!(track.to_it(), Err(Error::OutOfRange { value: 65, .. })));
+
+    // Every note filled in on every row and channel doesn't fit in the 16-bit size IT uses for patterns.
+    let mut pattern = Pattern::new(64, 200);
+    for row in 0..200 {
+        for channel in 0..64 {
+            pattern.set_note(channel, row, Note::new(PianoKey::C, (row % 10) as u8, Some((row % 2) as u8), Some(row as u8 % 64), Effect::Vibrato(row as u8)));
+        }
+    }
+
+    track.patterns[1] = pattern;
+    assert!(matches!(track.to_it(), Err(Error::OutOfRange { name: "IT pattern size", .. })));
+}
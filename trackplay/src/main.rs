@@ -1,17 +1,19 @@
 use std::time::Duration;
 
-use polymod::{self, track::{Track}, track_player::{TrackPlayer}};
+use polymod::{self, track::{Track}, track_player::{TrackPlayer, PlaybackState}, convert, ModuleType};
 use sdl2::audio::{AudioSpecDesired, AudioCallback};
-use clap::Parser;
-
-use crate::binary::BinaryWriter;
-
-mod binary;
+use clap::{Parser, Subcommand};
+use polymod::utils::binary::BinaryWriter;
 
 #[derive(Parser)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
     /// Path to the file.
-    path: String,
+    #[arg(required = true)]
+    path: Option<String>,
 
     /// The pitch tuning, where 1.0 is no change.
     #[arg(long, default_value_t = 1.0)]
@@ -25,130 +27,153 @@ struct Args {
     #[arg(long, default_value_t = 0.0)]
     start: f64,
 
+    /// The sample rate to play or render at.
+    #[arg(long, default_value_t = polymod::track_player::DEFAULT_SAMPLE_RATE)]
+    sample_rate: i32,
+
+    /// Channels to mute, separated by commas. Channels are numbered from 1.
+    #[arg(long, value_delimiter = ',', value_parser = clap::value_parser!(u16).range(1..))]
+    mute: Vec<u16>,
+
+    /// Channels to solo, separated by commas. Channels are numbered from 1.
+    #[arg(long, value_delimiter = ',', value_parser = clap::value_parser!(u16).range(1..))]
+    solo: Vec<u16>,
+
     /// Disable interpolation.
     #[arg(long, default_value_t = false)]
     no_interpolation: bool,
 
+    /// Print information about the track instead of playing it.
+    #[arg(long, default_value_t = false)]
+    info: bool,
+
+    /// The number of times to loop the song when rendering.
+    #[arg(long, default_value_t = 0)]
+    loops: u32,
+
     /// If set, the output will be redirected to the given file.
     #[arg(long)]
-    render: Option<String>
+    render: Option<String>,
+
+    /// If set, each channel will be rendered to its own file in the given directory.
+    #[arg(long)]
+    stems: Option<String>
 }
 
-struct Audio<'a> {
-    player: &'a mut TrackPlayer<'a>
+#[derive(Subcommand)]
+enum Command {
+    /// Convert a track to another format, picked from the output's extension.
+    Convert {
+        input: String,
+        output: String
+    }
 }
 
-impl<'a> AudioCallback for Audio<'a> {
+struct Audio {
+    player: TrackPlayer<'static>
+}
+
+impl AudioCallback for Audio {
     type Channel = f32;
 
     fn callback(&mut self, out: &mut [Self::Channel]) {
-        for x in out.iter_mut() {
-            *x = self.player.advance() as f32;
+        let frames = self.player.render(out);
+        for x in out[frames * 2..].iter_mut() {
+            *x = 0.0;
         }
     }
 }
 
 fn main() {
+    // Logs go to stderr, and can be turned on with RUST_LOG, e.g. RUST_LOG=polymod=debug
+    env_logger::init();
+
     let args = Args::parse();
-    let path = args.path.as_str();
+
+    if let Some(Command::Convert { input, output }) = &args.command {
+        convert_track(input, output);
+        return;
+    }
+
+    let track = load_track(args.path.as_deref().unwrap());
+
     let pitch_tuning = args.pitch;
     let tempo_tuning = args.tempo;
     let start = args.start;
+    let sample_rate = args.sample_rate;
 
-    let track = Track::from_it(&std::fs::read(path).unwrap());
-    if let Some(err) = track.as_ref().err() {
-        if err.kind() == std::io::ErrorKind::NotFound {
-            println!("The path \"{path}\" was not found.");
-            std::process::exit(1);
-        }
+    if args.info {
+        print_info(&track);
+        return;
     }
 
-    let track = track.unwrap();
+    let length_in_seconds = track.length_in_seconds;
 
-    let mut player = TrackPlayer::new(&track);
+    let mut player = TrackPlayer::new(track, sample_rate);
     player.set_pitch_tuning(pitch_tuning);
     player.set_tempo_tuning(tempo_tuning);
     player.set_interpolation(if args.no_interpolation { mixr::InterpolationType::None } else { mixr::InterpolationType::Linear });
 
-    player.seek_seconds(start);
-
-    if let Some(render) = args.render {
-        println!("Saving to {render}...");
-
-        let mut writer = BinaryWriter::new();
-
-        // "RIFF"
-        writer.write_u32(0x46464952);
-
-        // File size, we'll go back and add this in later.
-        writer.write_u32(0x0);
-
-        // "WAVE"
-        writer.write_u32(0x45564157);
-
-        // "fmt "
-        writer.write_u32(0x20746D66);
-
-        // Subchunk1 size, 16 for PCM in this case.
-        writer.write_u32(16);
-
-        // Format of 3, as floating point audio. 1 would be non floating point.
-        writer.write_u16(3);
-
-        // 2 as stereo.
-        writer.write_u16(2);
-
-        // Sampling rate.
-        writer.write_u32(polymod::track_player::SAMPLE_RATE as u32);
+    // Channels are numbered from 1 everywhere the user sees them, but from 0 in the player.
+    for channel in args.mute {
+        player.set_channel_muted(channel - 1, true);
+    }
 
-        // Byte rate = sample rate * channels(2) * bits_per_sample(32) / 8
-        writer.write_u32(polymod::track_player::SAMPLE_RATE as u32 * 8);
+    for channel in args.solo {
+        player.set_channel_solo(channel - 1, true);
+    }
 
-        // Block align = channels(2) * bits_per_sample(32) / 8
-        writer.write_u16(8);
+    player.seek_seconds(start);
 
-        // Bits per sample.
-        writer.write_u16(32);
+    if args.render.is_some() || args.stems.is_some() {
+        // The player tells us exactly when the song ends, the length here is only an estimate for progress reporting.
+        // Multiply this by 2 as we're in stereo.
+        let length_in_samples = (length_in_seconds * (args.loops + 1) as f64 * (1.0 / tempo_tuning) * sample_rate as f64) as usize * 2;
 
-        // "data"
-        writer.write_u32(0x61746164);
+        player.set_max_loops(Some(args.loops));
+        player.set_stems_enabled(args.stems.is_some());
 
-        // Multiply this by 2 as we're in stereo.
-        let length_in_samples = (track.length_in_seconds * (1.0 / tempo_tuning) * polymod::track_player::SAMPLE_RATE as f64) as usize * 2;
+        let mut output = Vec::with_capacity(length_in_samples);
+        let mut block = vec![0.0f32; 8192];
 
-        let mut output = Vec::with_capacity(length_in_samples * 4);
+        let mut stem_outputs = vec![Vec::new(); player.num_stems()];
+        let mut stem_blocks = vec![vec![0.0f32; block.len()]; player.num_stems()];
 
         println!("Rendering track...");
 
-        for i in 0..length_in_samples {
-            let samples = (player.advance() as f32).to_le_bytes();
+        let mut i = 0;
+        while player.state() != PlaybackState::Finished {
+            let frames = player.render_stems(&mut block, &mut stem_blocks);
 
-            output.push(samples[0]);
-            output.push(samples[1]);
-            output.push(samples[2]);
-            output.push(samples[3]);
+            output.extend_from_slice(&block[..frames * 2]);
+            for (stem_output, stem_block) in stem_outputs.iter_mut().zip(stem_blocks.iter()) {
+                stem_output.extend_from_slice(&stem_block[..frames * 2]);
+            }
 
-            if i % 500000 == 0 {
+            // Report progress roughly every 500000 samples.
+            if i / 500000 != (i + frames * 2) / 500000 {
                 println!("{i} / {length_in_samples} ({:.2}%)", (i as f64 / length_in_samples as f64) * 100.0);
             }
-        }
 
-        println!("{length_in_samples} / {length_in_samples} (100.00%)");
+            i += frames * 2;
+        }
 
-        println!("Writing result...");
+        println!("{i} / {i} (100.00%)");
 
-        // Subchunk2 size, in this case the PCM data length in bytes.
-        writer.write_u32(output.len() as u32);
-        writer.write_bytes(&output);
+        if let Some(render) = args.render {
+            println!("Saving to {render}...");
+            write_wav(&render, sample_rate, &output);
+        }
 
-        // Get current position (which is the number of bytes total), then write this value (-8) to the
-        // dummy value we entered earlier.
-        let position = writer.position() - 8;
-        writer.set_position(4);
-        writer.write_u32(position as u32);
+        if let Some(stems) = args.stems {
+            std::fs::create_dir_all(&stems).unwrap();
 
-        println!("Saving...");
-        std::fs::write(render.as_str(), writer.get_data()).unwrap();
+            for (channel, stem_output) in stem_outputs.iter().enumerate() {
+                let path = std::path::Path::new(&stems).join(format!("channel_{:02}.wav", channel + 1));
+                println!("Saving to {}...", path.display());
+                write_wav(path.to_str().unwrap(), sample_rate, stem_output);
+            }
+        }
 
         println!("Done!");
 
@@ -159,14 +184,14 @@ fn main() {
     let audio = sdl.audio().unwrap();
 
     let desired_spec = AudioSpecDesired {
-        freq: Some(polymod::track_player::SAMPLE_RATE),
+        freq: Some(sample_rate),
         channels: Some(2),
         samples: Some(512)
     };
 
     let device = audio.open_playback(None, &desired_spec, |_| {
         Audio {
-            player: &mut player
+            player
         }
     }).unwrap();
 
@@ -180,4 +205,140 @@ fn main() {
     loop {
         std::thread::sleep(Duration::from_secs(5));
     }
-}
\ No newline at end of file
+}
+
+/// Get the format of a file from its extension.
+fn module_type(path: &str) -> Option<ModuleType> {
+    std::path::Path::new(path).extension().and_then(|ext| ext.to_str()).and_then(ModuleType::from_extension)
+}
+
+fn load_track(path: &str) -> Track {
+    let data = match std::fs::read(path) {
+        Ok(data) => data,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+            println!("The path \"{path}\" was not found.");
+            std::process::exit(1);
+        },
+        Err(err) => {
+            println!("Failed to read \"{path}\": {err}");
+            std::process::exit(1);
+        }
+    };
+
+    // Anything we don't recognise is assumed to be IT, as that's what most tracks are.
+    match convert::load(&data, module_type(path).unwrap_or(ModuleType::IT)) {
+        Ok(track) => track,
+        Err(err) => {
+            println!("Failed to load \"{path}\": {err}");
+            std::process::exit(1);
+        }
+    }
+}
+
+fn convert_track(input: &str, output: &str) {
+    let Some(format) = module_type(output) else {
+        println!("Can't tell what format \"{output}\" should be from its extension.");
+        std::process::exit(1);
+    };
+
+    let track = load_track(input);
+    let conversion = convert::convert(&track, format);
+
+    for warning in &conversion.warnings {
+        println!("Warning: {warning}");
+    }
+
+    let data = match convert::save(&conversion.track, format) {
+        Ok(data) => data,
+        Err(err) => {
+            println!("Failed to convert to {}: {err}", format.name());
+            std::process::exit(1);
+        }
+    };
+
+    std::fs::write(output, data).unwrap();
+    println!("Converted to {} with {} warning(s).", format.name(), conversion.warnings.len());
+}
+
+fn print_info(track: &Track) {
+    let metadata = &track.metadata;
+
+    println!("Title: {}", metadata.title);
+    if let Some(artist) = &metadata.artist {
+        println!("Artist: {artist}");
+    }
+    println!("Tracker: {}", metadata.tracker);
+
+    let length = track.length_in_seconds as u64;
+    println!("Length: {}:{:02}", length / 60, length % 60);
+    println!("Orders: {}, Patterns: {}, Channels: {}", track.orders.len(), track.patterns.len(), track.num_channels());
+    println!("Speed: {}, Tempo: {}", track.speed, track.tempo);
+    println!("Highlight: {}/{}", metadata.highlight.0, metadata.highlight.1);
+
+    println!("\nSamples:");
+    for (i, sample) in metadata.samples.iter().enumerate() {
+        println!("{:>3}. {:<26} {}", i + 1, sample.name, sample.filename);
+    }
+
+    if !metadata.message.is_empty() {
+        println!("\nMessage:\n{}", metadata.message);
+    }
+}
+
+/// Write 32-bit floating point stereo audio to a WAV file.
+fn write_wav(path: &str, sample_rate: i32, samples: &[f32]) {
+    let mut writer = BinaryWriter::new();
+
+    // "RIFF"
+    writer.write_u32(0x46464952);
+
+    // File size, we'll go back and add this in later.
+    writer.write_u32(0x0);
+
+    // "WAVE"
+    writer.write_u32(0x45564157);
+
+    // "fmt "
+    writer.write_u32(0x20746D66);
+
+    // Subchunk1 size, 16 for PCM in this case.
+    writer.write_u32(16);
+
+    // Format of 3, as floating point audio. 1 would be non floating point.
+    writer.write_u16(3);
+
+    // 2 as stereo.
+    writer.write_u16(2);
+
+    // Sampling rate.
+    writer.write_u32(sample_rate as u32);
+
+    // Byte rate = sample rate * channels(2) * bits_per_sample(32) / 8
+    writer.write_u32(sample_rate as u32 * 8);
+
+    // Block align = channels(2) * bits_per_sample(32) / 8
+    writer.write_u16(8);
+
+    // Bits per sample.
+    writer.write_u16(32);
+
+    // "data"
+    writer.write_u32(0x61746164);
+
+    let mut output = Vec::with_capacity(samples.len() * 4);
+    for sample in samples {
+        output.extend_from_slice(&sample.to_le_bytes());
+    }
+
+    // Subchunk2 size, in this case the PCM data length in bytes.
+    writer.write_u32(output.len() as u32);
+    writer.write_bytes(&output);
+
+    // Get current position (which is the number of bytes total), then write this value (-8) to the
+    // dummy value we entered earlier.
+    let position = writer.position() - 8;
+    writer.set_position(4);
+    writer.write_u32(position as u32);
+
+    std::fs::write(path, writer.get_data()).unwrap();
+}